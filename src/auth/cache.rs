@@ -0,0 +1,141 @@
+//! Reuse of auth tokens across requests
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::debug;
+use crate::auth::keystore::Signer;
+use crate::auth::session::SessionLogin;
+use crate::auth::AuthManager;
+use crate::error::Result;
+
+/// Default token lifetime
+pub const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Default fraction of the lifetime after which a token is replaced
+pub const DEFAULT_REFRESH_FRACTION: f64 = 0.8;
+
+/// Longest a token is cached, whatever lifetime the server reports
+pub const MAX_CACHED_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Caches the token sent with every request
+///
+/// Tokens are either minted by an [`AuthManager`] or, with a
+/// [`SessionLogin`], issued by the server. Either way the same token is
+/// handed out until `refresh_fraction` of its lifetime has passed, leaving
+/// the rest as margin for clock skew and slow requests.
+pub struct TokenCache {
+    auth_manager: Arc<AuthManager>,
+    session: Option<SessionLogin>,
+    lifetime: Duration,
+    refresh_fraction: f64,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+struct CachedToken {
+    token: String,
+    signer: Arc<dyn Signer>,
+    refresh_at: Instant,
+}
+
+impl TokenCache {
+    /// Create a cache minting tokens valid for `lifetime`
+    ///
+    /// `refresh_fraction` is clamped to `0.0..=1.0`; `0.0` disables caching.
+    pub fn new(auth_manager: Arc<AuthManager>, lifetime: Duration, refresh_fraction: f64) -> Self {
+        Self {
+            auth_manager,
+            session: None,
+            lifetime,
+            refresh_fraction: refresh_fraction.clamp(0.0, 1.0),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Obtain session tokens through a challenge-response login instead of
+    /// minting them; their lifetime is set by the server
+    pub fn with_session_login(mut self, login: SessionLogin) -> Self {
+        self.session = Some(login);
+        self
+    }
+
+    /// Get the auth manager tokens are minted with
+    pub fn auth_manager(&self) -> &Arc<AuthManager> {
+        &self.auth_manager
+    }
+
+    /// Lifetime of minted tokens
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Get a valid token, replacing the cached one if it is due for refresh
+    ///
+    /// A token obtained with a key that has since been rotated out is
+    /// replaced too. Concurrent callers wait for a single login.
+    pub async fn token(&self) -> Result<String> {
+        let mut cached = self.cached.lock().await;
+        let signer = self.auth_manager.signer();
+
+        if let Some(entry) = cached.as_ref() {
+            if Instant::now() < entry.refresh_at && std::ptr::addr_eq(Arc::as_ptr(&entry.signer), Arc::as_ptr(&signer)) {
+                return Ok(entry.token.clone());
+            }
+        }
+
+        let (token, lifetime) = match &self.session {
+            Some(login) => {
+                let session = login.login(&self.auth_manager).await?;
+                let lifetime = session.lifetime();
+                (session.token, lifetime)
+            },
+            None => {
+                debug!("Minting new auth token");
+                (self.auth_manager.create_auth_token(self.lifetime.as_secs())?, self.lifetime)
+            }
+        };
+
+        *cached = Some(CachedToken {
+            token: token.clone(),
+            signer,
+            refresh_at: refresh_at(Instant::now(), lifetime, self.refresh_fraction),
+        });
+
+        Ok(token)
+    }
+
+    /// Drop the cached token, e.g. after the server rejected it
+    pub async fn invalidate(&self) {
+        self.cached.lock().await.take();
+    }
+}
+
+/// When a token obtained at `now` is replaced
+///
+/// The lifetime is capped at [`MAX_CACHED_LIFETIME`] so a bogus
+/// server-supplied `expires_in` can't overflow the clock.
+fn refresh_at(now: Instant, lifetime: Duration, refresh_fraction: f64) -> Instant {
+    let refresh_in = lifetime.min(MAX_CACHED_LIFETIME).mul_f64(refresh_fraction);
+    now.checked_add(refresh_in)
+        .or_else(|| now.checked_add(MAX_CACHED_LIFETIME))
+        .unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_at_applies_the_fraction() {
+        let now = Instant::now();
+        assert_eq!(refresh_at(now, Duration::from_secs(100), 0.8), now + Duration::from_secs(80));
+        assert_eq!(refresh_at(now, Duration::from_secs(100), 0.0), now);
+    }
+
+    #[test]
+    fn refresh_at_clamps_huge_lifetimes() {
+        let now = Instant::now();
+        assert_eq!(refresh_at(now, Duration::from_secs(u64::MAX), 0.8), now + MAX_CACHED_LIFETIME.mul_f64(0.8));
+        assert_eq!(refresh_at(now, Duration::MAX, 1.0), now + MAX_CACHED_LIFETIME);
+    }
+}
@@ -0,0 +1,220 @@
+//! Passphrase-encrypted private key files
+//!
+//! Keys are sealed in a small JSON envelope: the passphrase is stretched with
+//! scrypt and the PKCS#8 DER key is encrypted with ChaCha20-Poly1305.
+
+use base64::{engine::general_purpose, Engine as _};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Serialize, Deserialize};
+use crate::error::{Result, SdkError};
+
+/// Value of the `format` field identifying an encrypted key file
+const FORMAT: &str = "iot-dash-encrypted-key";
+
+/// Current envelope version
+const VERSION: u32 = 1;
+
+/// scrypt cost used for new files (N = 2^15, about 32 MiB of memory)
+const DEFAULT_LOG_N: u8 = 15;
+const DEFAULT_R: u32 = 8;
+const DEFAULT_P: u32 = 1;
+
+/// Most memory scrypt may use when reading (128 * r * 2^log_n bytes), so a
+/// crafted file can't exhaust the device's memory; 8 times the default
+const MAX_MEMORY: u128 = 256 * 1024 * 1024;
+
+/// Most work scrypt may do when reading (p * r * 2^log_n), so a crafted
+/// file can't tie up the CPU; 16 times the default
+const MAX_WORK: u128 = 1 << 22;
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// On-disk envelope of an encrypted key
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    format: String,
+    version: u32,
+    kdf: KdfParams,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// scrypt parameters and salt
+#[derive(Serialize, Deserialize)]
+struct KdfParams {
+    name: String,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+/// Encrypt a PKCS#8 DER private key with a passphrase
+pub fn encrypt_private_key(pkcs8: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let rng = SystemRandom::new();
+
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| SdkError::Key("Failed to generate random bytes".to_string()))?;
+
+    let kdf = KdfParams {
+        name: "scrypt".to_string(),
+        log_n: DEFAULT_LOG_N,
+        r: DEFAULT_R,
+        p: DEFAULT_P,
+        salt: general_purpose::STANDARD.encode(salt),
+    };
+
+    let key = cipher_key(passphrase, &kdf)?;
+    let mut in_out = pkcs8.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(FORMAT), &mut in_out)
+        .map_err(|_| SdkError::Key("Failed to encrypt private key".to_string()))?;
+
+    let envelope = Envelope {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        kdf,
+        cipher: "chacha20-poly1305".to_string(),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(in_out),
+    };
+
+    Ok(serde_json::to_vec_pretty(&envelope)?)
+}
+
+/// Decrypt a key written by [`encrypt_private_key`], returning PKCS#8 DER
+pub fn decrypt_private_key(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let envelope = parse_envelope(data)
+        .ok_or_else(|| SdkError::Key("Not an encrypted private key file".to_string()))?;
+
+    if envelope.version != VERSION {
+        return Err(SdkError::Key(format!("Unsupported encrypted key version {}", envelope.version)));
+    }
+    if envelope.cipher != "chacha20-poly1305" {
+        return Err(SdkError::Key(format!("Unsupported key cipher {}", envelope.cipher)));
+    }
+
+    let nonce: [u8; NONCE_LEN] = general_purpose::STANDARD.decode(&envelope.nonce)
+        .ok()
+        .and_then(|nonce| nonce.try_into().ok())
+        .ok_or_else(|| SdkError::Key("Invalid nonce in encrypted key".to_string()))?;
+    let mut in_out = general_purpose::STANDARD.decode(&envelope.ciphertext)
+        .map_err(|_| SdkError::Key("Invalid ciphertext in encrypted key".to_string()))?;
+
+    let key = cipher_key(passphrase, &envelope.kdf)?;
+    let plaintext = key.open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(FORMAT), &mut in_out)
+        .map_err(|_| SdkError::Key("Wrong passphrase or corrupted key file".to_string()))?;
+
+    Ok(plaintext.to_vec())
+}
+
+/// Whether `data` is an encrypted key envelope
+pub fn is_encrypted(data: &[u8]) -> bool {
+    parse_envelope(data).is_some()
+}
+
+fn parse_envelope(data: &[u8]) -> Option<Envelope> {
+    serde_json::from_slice::<Envelope>(data)
+        .ok()
+        .filter(|envelope| envelope.format == FORMAT)
+}
+
+/// Reject scrypt parameters whose memory or CPU cost exceeds the limits
+fn check_cost(kdf: &KdfParams) -> Result<()> {
+    let exceeded = || SdkError::Key(format!(
+        "scrypt parameters log_n={} r={} p={} exceed the supported cost", kdf.log_n, kdf.r, kdf.p
+    ));
+
+    // With log_n below 64, r * 2^log_n times 128 or p still fits a u128
+    if kdf.log_n >= 64 {
+        return Err(exceeded());
+    }
+    let block_mix = u128::from(kdf.r) << kdf.log_n;
+
+    if 128 * block_mix > MAX_MEMORY || u128::from(kdf.p) * block_mix > MAX_WORK {
+        return Err(exceeded());
+    }
+    Ok(())
+}
+
+/// Derive the AEAD key from the passphrase
+fn cipher_key(passphrase: &str, kdf: &KdfParams) -> Result<LessSafeKey> {
+    if kdf.name != "scrypt" {
+        return Err(SdkError::Key(format!("Unsupported key derivation {}", kdf.name)));
+    }
+    check_cost(kdf)?;
+
+    let salt = general_purpose::STANDARD.decode(&kdf.salt)
+        .map_err(|_| SdkError::Key("Invalid salt in encrypted key".to_string()))?;
+    let params = scrypt::Params::new(kdf.log_n, kdf.r, kdf.p, KEY_LEN)
+        .map_err(|e| SdkError::Key(format!("Invalid scrypt parameters: {}", e)))?;
+
+    let mut key = [0u8; KEY_LEN];
+    scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key)
+        .map_err(|e| SdkError::Key(format!("Key derivation failed: {}", e)))?;
+
+    let unbound = UnboundKey::new(&CHACHA20_POLY1305, &key)
+        .map_err(|_| SdkError::Key("Failed to create cipher key".to_string()))?;
+    Ok(LessSafeKey::new(unbound))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kdf(log_n: u8, r: u32, p: u32) -> KdfParams {
+        KdfParams {
+            name: "scrypt".to_string(),
+            log_n,
+            r,
+            p,
+            salt: general_purpose::STANDARD.encode([0u8; SALT_LEN]),
+        }
+    }
+
+    /// An envelope asking for the given scrypt parameters
+    fn envelope(log_n: u8, r: u32, p: u32) -> Vec<u8> {
+        serde_json::to_vec(&Envelope {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            kdf: kdf(log_n, r, p),
+            cipher: "chacha20-poly1305".to_string(),
+            nonce: general_purpose::STANDARD.encode([0u8; NONCE_LEN]),
+            ciphertext: general_purpose::STANDARD.encode([0u8; 48]),
+        }).unwrap()
+    }
+
+    #[test]
+    fn accepts_default_and_limit_costs() {
+        assert!(check_cost(&kdf(DEFAULT_LOG_N, DEFAULT_R, DEFAULT_P)).is_ok());
+        assert!(check_cost(&kdf(18, 8, 2)).is_ok());
+        assert!(check_cost(&kdf(21, 1, 2)).is_ok());
+    }
+
+    #[test]
+    fn rejects_excessive_costs() {
+        // 4 GiB of memory, within the old per-parameter caps
+        assert!(check_cost(&kdf(20, 32, 1)).is_err());
+        assert!(check_cost(&kdf(20, 1 << 20, 1)).is_err());
+        assert!(check_cost(&kdf(19, 8, 1)).is_err());
+        // Little memory but a lot of work
+        assert!(check_cost(&kdf(10, 1, 1 << 16)).is_err());
+        assert!(check_cost(&kdf(18, 8, 3)).is_err());
+        assert!(check_cost(&kdf(64, 1, 1)).is_err());
+        assert!(check_cost(&kdf(255, u32::MAX, u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn decrypt_rejects_excessive_costs_before_deriving() {
+        // Running scrypt with these would allocate 4 GiB or spin for minutes
+        for data in [envelope(20, 32, 1), envelope(12, 8, u32::MAX)] {
+            let error = decrypt_private_key(&data, "passphrase").unwrap_err();
+            assert!(error.to_string().contains("exceed the supported cost"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Persistent device identity (key pair and device ID)
+
+use ring::signature::Ed25519KeyPair;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use crate::auth::generate_device_id;
+use crate::auth::keystore::generate_pkcs8;
+use crate::error::{Result, SdkError};
+
+/// File holding the PKCS#8 private key
+const KEY_FILE: &str = "device.key";
+
+/// File holding the device ID
+const DEVICE_ID_FILE: &str = "device_id";
+
+/// A device's key pair and ID
+#[derive(Clone)]
+pub struct DeviceIdentity {
+    /// Device identifier
+    pub device_id: String,
+
+    /// Ed25519 private key as PKCS#8 DER
+    pub pkcs8: Vec<u8>,
+}
+
+impl std::fmt::Debug for DeviceIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceIdentity")
+            .field("device_id", &self.device_id)
+            .field("pkcs8", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Stores the device identity in a directory so it survives restarts
+pub struct IdentityStore {
+    dir: PathBuf,
+}
+
+impl IdentityStore {
+    /// Create a store backed by the given directory
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self { dir: dir.as_ref().to_path_buf() }
+    }
+
+    /// Directory the identity is stored in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Load the stored identity, if there is one
+    ///
+    /// A stored key without a device ID is an error rather than a missing
+    /// identity, so it is never overwritten by a freshly generated one.
+    pub fn load(&self) -> Result<Option<DeviceIdentity>> {
+        let pkcs8 = match fs::read(self.dir.join(KEY_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(SdkError::Key(format!("Failed to read stored private key: {}", e))),
+        };
+
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8)
+            .map_err(|_| SdkError::Key("Stored private key is invalid".to_string()))?;
+
+        let device_id = match fs::read_to_string(self.dir.join(DEVICE_ID_FILE)) {
+            Ok(id) if !id.trim().is_empty() => id.trim().to_string(),
+            // The ID is written before the key, so a key without an ID means
+            // the directory is damaged; never replace the key silently
+            Ok(_) => return Err(SdkError::Key(format!(
+                "Stored device ID in {} is empty", self.dir.display()
+            ))),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(SdkError::Key(format!(
+                "Private key in {} has no stored device ID", self.dir.display()
+            ))),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(DeviceIdentity { device_id, pkcs8 }))
+    }
+
+    /// Persist an identity, replacing any stored one
+    ///
+    /// Each file is written to a temporary file with owner-only permissions
+    /// and renamed into place, so a crash never leaves a half-written key.
+    /// The device ID goes first: the key's presence marks a complete identity,
+    /// and a crash in between leaves no key that could be lost.
+    pub fn save(&self, identity: &DeviceIdentity) -> Result<()> {
+        create_private_dir(&self.dir)?;
+        write_private_file(&self.dir.join(DEVICE_ID_FILE), identity.device_id.as_bytes())?;
+        write_private_file(&self.dir.join(KEY_FILE), &identity.pkcs8)?;
+        Ok(())
+    }
+
+    /// Load the stored identity, generating and saving a new one on first boot
+    ///
+    /// A new identity uses `device_id` if given, or a random ID otherwise.
+    pub fn load_or_create(&self, device_id: Option<&str>) -> Result<DeviceIdentity> {
+        if let Some(identity) = self.load()? {
+            return Ok(identity);
+        }
+
+        let identity = DeviceIdentity {
+            device_id: device_id.map(str::to_string).unwrap_or_else(generate_device_id),
+            pkcs8: generate_pkcs8()?,
+        };
+
+        self.save(&identity)?;
+        info!("Generated new device identity {} in {}", identity.device_id, self.dir.display());
+
+        Ok(identity)
+    }
+}
+
+/// Create a directory readable only by its owner
+fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)?;
+    Ok(())
+}
+
+/// Atomically write a file readable only by its owner
+pub(crate) fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)?;
+
+    // Make the rename itself durable. The new contents are already in place,
+    // so a failure here is only logged: callers treat an error as "not
+    // written" and must not act on the old contents
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        // A bare file name has an empty parent, meaning the current directory
+        let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+        if let Err(e) = fs::File::open(parent).and_then(|dir| dir.sync_all()) {
+            warn!("Failed to sync directory {} after writing {}: {}", parent.display(), path.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Serializes tests that change the process-wide working directory
+    static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Run `f` with a fresh, empty temporary directory as the working directory
+    pub(crate) fn in_temp_dir<T>(name: &str, f: impl FnOnce() -> T) -> T {
+        let _guard = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("iot-sdk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let previous = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+        std::env::set_current_dir(previous).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        result.unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+
+    #[test]
+    fn write_private_file_accepts_bare_file_name() {
+        in_temp_dir("bare-name", || {
+            write_private_file(Path::new("device.key"), b"secret").unwrap();
+            assert_eq!(fs::read("device.key").unwrap(), b"secret");
+            assert!(!Path::new("device.tmp").exists());
+        });
+    }
+}
@@ -0,0 +1,107 @@
+//! Compact JWS/JWT encoding for device tokens
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::error::Result;
+
+/// JWS algorithm name for Ed25519 signatures
+pub const ALGORITHM: &str = "EdDSA";
+
+/// Format of tokens minted by [`AuthManager`](crate::auth::AuthManager)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenFormat {
+    /// `base64(json "." base64(signature))`, understood by older dashboards
+    #[default]
+    Legacy,
+
+    /// Compact JWS with `alg: EdDSA`, verifiable by standard JWT libraries
+    Jwt,
+}
+
+/// JOSE header of a device token
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Header {
+    pub alg: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+}
+
+/// Claims carried by a device token
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Claims {
+    /// Device the token was issued for
+    pub device_id: String,
+
+    /// Subject, the device ID in JWT tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+
+    /// Issued-at time, in seconds since the UNIX epoch
+    pub iat: u64,
+
+    /// Expiration time, in seconds since the UNIX epoch
+    pub exp: u64,
+
+    /// Not-before time, in seconds since the UNIX epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+
+    /// Intended audience
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+
+    /// Unique token ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+/// Key ID for an Ed25519 public key
+///
+/// This is the RFC 7638 JWK thumbprint, so it matches the `kid` servers
+/// compute from the device's registered JWK.
+pub fn key_id(public_key: &[u8]) -> String {
+    // Members in lexicographic order with no whitespace, as RFC 7638 requires
+    let jwk = format!(
+        r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+        general_purpose::URL_SAFE_NO_PAD.encode(public_key)
+    );
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.as_bytes()))
+}
+
+/// Encode and sign a compact JWS
+pub(crate) fn encode<F>(header: &Header, claims: &Claims, sign: F) -> Result<String>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>>,
+{
+    let header_b64 = general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(header)?);
+    let claims_b64 = general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let signing_input = format!("{}.{}", header_b64, claims_b64);
+
+    let signature = sign(signing_input.as_bytes())?;
+    let signature_b64 = general_purpose::URL_SAFE_NO_PAD.encode(signature);
+
+    Ok(format!("{}.{}", signing_input, signature_b64))
+}
+
+/// Split a compact JWS into its header, claims, signing input and signature
+pub(crate) fn decode(token: &str) -> Option<(Header, Claims, String, Vec<u8>)> {
+    let mut parts = token.split('.');
+    let (Some(header_b64), Some(claims_b64), Some(signature_b64), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return None;
+    };
+
+    let decode_part = |part: &str| general_purpose::URL_SAFE_NO_PAD.decode(part).ok();
+
+    let header: Header = serde_json::from_slice(&decode_part(header_b64)?).ok()?;
+    let claims: Claims = serde_json::from_slice(&decode_part(claims_b64)?).ok()?;
+    let signature = decode_part(signature_b64)?;
+
+    Some((header, claims, format!("{}.{}", header_b64, claims_b64), signature))
+}
@@ -0,0 +1,421 @@
+//! Pluggable sources of signing keys
+//!
+//! A [`KeyStore`] produces a [`Signer`], which signs messages on behalf of
+//! the device. Signers never have to expose the private key, so hardware or
+//! emulated secure elements fit behind the same interface as key files.
+
+use base64::{engine::general_purpose, Engine as _};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use crate::auth::encrypted;
+use crate::auth::identity::{write_private_file, DeviceIdentity, IdentityStore};
+use crate::auth::keys::{private_key_der, private_key_to_pem};
+use crate::error::{Result, SdkError};
+
+/// Signs messages with a device's Ed25519 key
+pub trait Signer: Send + Sync {
+    /// Raw Ed25519 public key
+    fn public_key(&self) -> &[u8];
+
+    /// Sign a message, returning the 64-byte signature
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Source of a device's signing key
+pub trait KeyStore: Send + Sync {
+    /// Load the key and return a signer for it
+    fn load_signer(&self) -> Result<Arc<dyn Signer>>;
+
+    /// Device ID kept alongside the key, if the store has one
+    fn device_id(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Whether [`KeyStore::save_key`] can replace the stored key
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /// Atomically replace the stored key, e.g. after a key rotation
+    ///
+    /// An error means the stored key was left unchanged.
+    fn save_key(&self, _device_id: &str, _pkcs8: &[u8]) -> Result<()> {
+        Err(SdkError::Key("Key store is read-only".to_string()))
+    }
+}
+
+/// Signer holding an Ed25519 key pair in process memory
+pub struct SoftwareSigner {
+    key_pair: Ed25519KeyPair,
+}
+
+impl SoftwareSigner {
+    /// Create a signer from a PKCS#8 DER private key
+    ///
+    /// Both PKCS#8 v1 (as written by openssl) and v2 keys are accepted.
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        Ok(Self { key_pair: parse_pkcs8(pkcs8)? })
+    }
+
+    /// Create a signer for a freshly generated key
+    pub fn generate() -> Result<Self> {
+        Self::from_pkcs8(&generate_pkcs8()?)
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.key_pair.sign(message).as_ref().to_vec())
+    }
+}
+
+/// Key stored as a PKCS#8 file, in DER or PEM encoding
+pub struct FileKeyStore {
+    path: PathBuf,
+}
+
+impl FileKeyStore {
+    /// Create a store reading the key from `path`
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn load_signer(&self) -> Result<Arc<dyn Signer>> {
+        let key_bytes = fs::read(&self.path)
+            .map_err(|e| SdkError::Key(format!("Failed to read private key file: {}", e)))?;
+        if encrypted::is_encrypted(&key_bytes) {
+            return Err(SdkError::Key("Private key file is encrypted; a passphrase is required".to_string()));
+        }
+        Ok(Arc::new(SoftwareSigner::from_pkcs8(&private_key_der(&key_bytes)?)?))
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    /// Replace the key file, keeping its PEM or DER encoding
+    fn save_key(&self, _device_id: &str, pkcs8: &[u8]) -> Result<()> {
+        let is_pem = fs::read(&self.path)
+            .map(|existing| existing.starts_with(b"-----BEGIN "))
+            .unwrap_or(false);
+
+        if is_pem {
+            write_private_file(&self.path, private_key_to_pem(pkcs8).as_bytes())
+        } else {
+            write_private_file(&self.path, pkcs8)
+        }
+    }
+}
+
+/// Where the passphrase of an encrypted key comes from
+#[derive(Clone)]
+pub enum Passphrase {
+    /// Passphrase given directly
+    Value(String),
+
+    /// Environment variable holding the passphrase
+    Env(String),
+
+    /// Function asked for the passphrase when the key is loaded
+    Callback(Arc<dyn Fn() -> Result<String> + Send + Sync>),
+}
+
+impl Passphrase {
+    /// Ask a function for the passphrase, e.g. to prompt the user
+    pub fn callback<F>(f: F) -> Self
+    where
+        F: Fn() -> Result<String> + Send + Sync + 'static,
+    {
+        Passphrase::Callback(Arc::new(f))
+    }
+
+    /// Get the passphrase
+    pub fn resolve(&self) -> Result<String> {
+        match self {
+            Passphrase::Value(passphrase) => Ok(passphrase.clone()),
+            Passphrase::Env(var) => std::env::var(var)
+                .map_err(|_| SdkError::Key(format!("Environment variable {} is not set", var))),
+            Passphrase::Callback(f) => f(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Passphrase::Value(_) => f.write_str("Value(<redacted>)"),
+            Passphrase::Env(var) => f.debug_tuple("Env").field(var).finish(),
+            Passphrase::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// Key stored in a passphrase-encrypted file
+///
+/// See [`encrypt_private_key`](crate::auth::encrypted::encrypt_private_key)
+/// for the file format.
+pub struct EncryptedFileKeyStore {
+    path: PathBuf,
+    passphrase: Passphrase,
+}
+
+impl EncryptedFileKeyStore {
+    /// Create a store for the encrypted key at `path`
+    pub fn new<P: Into<PathBuf>>(path: P, passphrase: Passphrase) -> Self {
+        Self { path: path.into(), passphrase }
+    }
+
+    /// Encrypt a PKCS#8 DER key and write it to the store's file
+    pub fn save(&self, pkcs8: &[u8]) -> Result<()> {
+        let data = encrypted::encrypt_private_key(pkcs8, &self.passphrase.resolve()?)?;
+        write_private_file(&self.path, &data)
+    }
+}
+
+impl KeyStore for EncryptedFileKeyStore {
+    fn load_signer(&self) -> Result<Arc<dyn Signer>> {
+        let data = fs::read(&self.path)
+            .map_err(|e| SdkError::Key(format!("Failed to read private key file: {}", e)))?;
+        let pkcs8 = encrypted::decrypt_private_key(&data, &self.passphrase.resolve()?)?;
+        Ok(Arc::new(SoftwareSigner::from_pkcs8(&pkcs8)?))
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn save_key(&self, _device_id: &str, pkcs8: &[u8]) -> Result<()> {
+        self.save(pkcs8)
+    }
+}
+
+/// Key given as a base64-encoded PKCS#8 string or a PEM block
+pub struct Base64KeyStore {
+    key: String,
+}
+
+impl Base64KeyStore {
+    /// Create a store from a base64 string
+    pub fn new<S: Into<String>>(key: S) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl KeyStore for Base64KeyStore {
+    fn load_signer(&self) -> Result<Arc<dyn Signer>> {
+        Ok(Arc::new(SoftwareSigner::from_pkcs8(&decode_base64_key(&self.key)?)?))
+    }
+}
+
+/// Key read from an environment variable holding base64-encoded PKCS#8 or PEM
+pub struct EnvKeyStore {
+    var: String,
+}
+
+impl EnvKeyStore {
+    /// Create a store reading the variable `var`
+    pub fn new<S: Into<String>>(var: S) -> Self {
+        Self { var: var.into() }
+    }
+}
+
+impl KeyStore for EnvKeyStore {
+    fn load_signer(&self) -> Result<Arc<dyn Signer>> {
+        let key = std::env::var(&self.var)
+            .map_err(|_| SdkError::Key(format!("Environment variable {} is not set", self.var)))?;
+        Ok(Arc::new(SoftwareSigner::from_pkcs8(&decode_base64_key(&key)?)?))
+    }
+}
+
+/// Key and device ID persisted in an [`IdentityStore`], created on first use
+pub struct IdentityKeyStore {
+    store: IdentityStore,
+    device_id: Option<String>,
+}
+
+impl IdentityKeyStore {
+    /// Create a store; `device_id` is used if a new identity has to be generated
+    pub fn new(store: IdentityStore, device_id: Option<String>) -> Self {
+        Self { store, device_id }
+    }
+}
+
+impl KeyStore for IdentityKeyStore {
+    fn load_signer(&self) -> Result<Arc<dyn Signer>> {
+        let identity = self.store.load_or_create(self.device_id.as_deref())?;
+        Ok(Arc::new(SoftwareSigner::from_pkcs8(&identity.pkcs8)?))
+    }
+
+    fn device_id(&self) -> Result<Option<String>> {
+        Ok(self.store.load()?.map(|identity| identity.device_id))
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn save_key(&self, device_id: &str, pkcs8: &[u8]) -> Result<()> {
+        self.store.save(&DeviceIdentity {
+            device_id: device_id.to_string(),
+            pkcs8: pkcs8.to_vec(),
+        })
+    }
+}
+
+/// Fresh random key that lives only as long as the process
+#[derive(Default)]
+pub struct EphemeralKeyStore;
+
+impl KeyStore for EphemeralKeyStore {
+    fn load_signer(&self) -> Result<Arc<dyn Signer>> {
+        Ok(Arc::new(SoftwareSigner::generate()?))
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    /// Nothing to persist; the new key lives in memory like the old one
+    fn save_key(&self, _device_id: &str, _pkcs8: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Opaque reference to a key held inside a [`SoftSecureElement`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyHandle(u32);
+
+/// Software emulation of a secure element or TPM
+///
+/// Keys are generated inside the element and only ever referenced through a
+/// [`KeyHandle`]; there is no way to read the private key back out. Useful for
+/// developing against the same interface as real hardware.
+#[derive(Default)]
+pub struct SoftSecureElement {
+    slots: RwLock<HashMap<KeyHandle, Ed25519KeyPair>>,
+    /// Next handle to hand out; handles are never reused after deletion
+    next_handle: AtomicU32,
+}
+
+impl SoftSecureElement {
+    /// Create an empty element
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate a key inside the element
+    pub fn generate_key(&self) -> Result<KeyHandle> {
+        let key_pair = Ed25519KeyPair::from_pkcs8(&generate_pkcs8()?)
+            .map_err(|_| SdkError::Key("Failed to parse generated key".to_string()))?;
+        Ok(self.store(key_pair))
+    }
+
+    /// Import a PKCS#8 key, after which it can only be used through its handle
+    pub fn import_pkcs8(&self, pkcs8: &[u8]) -> Result<KeyHandle> {
+        Ok(self.store(parse_pkcs8(pkcs8)?))
+    }
+
+    /// Public key of a stored key
+    pub fn public_key(&self, handle: KeyHandle) -> Result<Vec<u8>> {
+        let slots = self.slots.read().unwrap();
+        let key_pair = slots.get(&handle)
+            .ok_or_else(|| SdkError::Key("Unknown key handle".to_string()))?;
+        Ok(key_pair.public_key().as_ref().to_vec())
+    }
+
+    /// Sign a message with a stored key
+    pub fn sign(&self, handle: KeyHandle, message: &[u8]) -> Result<Vec<u8>> {
+        let slots = self.slots.read().unwrap();
+        let key_pair = slots.get(&handle)
+            .ok_or_else(|| SdkError::Key("Unknown key handle".to_string()))?;
+        Ok(key_pair.sign(message).as_ref().to_vec())
+    }
+
+    /// Destroy a stored key
+    pub fn delete_key(&self, handle: KeyHandle) -> bool {
+        self.slots.write().unwrap().remove(&handle).is_some()
+    }
+
+    fn store(&self, key_pair: Ed25519KeyPair) -> KeyHandle {
+        let handle = KeyHandle(self.next_handle.fetch_add(1, Ordering::Relaxed));
+        self.slots.write().unwrap().insert(handle, key_pair);
+        handle
+    }
+}
+
+/// Signer backed by a key inside a [`SoftSecureElement`]
+pub struct SecureElementSigner {
+    element: Arc<SoftSecureElement>,
+    handle: KeyHandle,
+    public_key: Vec<u8>,
+}
+
+impl SecureElementSigner {
+    /// Create a signer for the key behind `handle`
+    pub fn new(element: Arc<SoftSecureElement>, handle: KeyHandle) -> Result<Self> {
+        let public_key = element.public_key(handle)?;
+        Ok(Self { element, handle, public_key })
+    }
+}
+
+impl Signer for SecureElementSigner {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        self.element.sign(self.handle, message)
+    }
+}
+
+/// Key held in a [`SoftSecureElement`] slot
+pub struct SecureElementKeyStore {
+    element: Arc<SoftSecureElement>,
+    handle: KeyHandle,
+}
+
+impl SecureElementKeyStore {
+    /// Create a store for the key behind `handle`
+    pub fn new(element: Arc<SoftSecureElement>, handle: KeyHandle) -> Self {
+        Self { element, handle }
+    }
+}
+
+impl KeyStore for SecureElementKeyStore {
+    fn load_signer(&self) -> Result<Arc<dyn Signer>> {
+        Ok(Arc::new(SecureElementSigner::new(self.element.clone(), self.handle)?))
+    }
+}
+
+/// Generate a new Ed25519 private key as PKCS#8 DER
+pub(crate) fn generate_pkcs8() -> Result<Vec<u8>> {
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
+        .map_err(|_| SdkError::Key("Failed to generate key pair".to_string()))?;
+    Ok(pkcs8.as_ref().to_vec())
+}
+
+/// Parse a PKCS#8 v1 or v2 Ed25519 private key
+fn parse_pkcs8(pkcs8: &[u8]) -> Result<Ed25519KeyPair> {
+    Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+        .map_err(|_| SdkError::Key("Invalid key format".to_string()))
+}
+
+/// Decode a private key given as base64 DER or as PEM text
+fn decode_base64_key(key: &str) -> Result<Vec<u8>> {
+    if key.trim_start().starts_with("-----BEGIN ") {
+        return private_key_der(key.as_bytes());
+    }
+    general_purpose::STANDARD.decode(key.trim())
+        .map_err(|_| SdkError::Key("Invalid base64 encoding for private key".to_string()))
+}
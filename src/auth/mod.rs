@@ -1,47 +1,109 @@
-use anyhow::{Result, Context};
-use ring::signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+pub mod cache;
+pub mod encrypted;
+pub mod identity;
+pub mod jwt;
+pub mod keys;
+pub mod keystore;
+pub mod rotation;
+pub mod session;
+pub mod verifier;
+
+use crate::error::{Result, SdkError};
+use ring::signature::{UnparsedPublicKey, ED25519};
 use base64::{engine::general_purpose, Engine as _};
 use rand::{rngs::OsRng, TryRngCore};
-use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::info;
 use crate::config::ClientConfig;
 
+pub use cache::TokenCache;
+pub use identity::{DeviceIdentity, IdentityStore};
+pub use jwt::{Claims, TokenFormat};
+pub use keys::{generate_keypair, KeyFormat, PublicKey};
+pub use keystore::{
+    Base64KeyStore, EncryptedFileKeyStore, EnvKeyStore, EphemeralKeyStore, FileKeyStore,
+    IdentityKeyStore, KeyHandle, KeyStore, Passphrase, SecureElementKeyStore, SecureElementSigner,
+    Signer, SoftSecureElement, SoftwareSigner,
+};
+pub use rotation::{KeyRotation, KeyRotationRequest};
+pub use session::{AuthFlow, SessionLogin, SessionToken};
+pub use verifier::{KeyRegistry, PublicKeyResolver, TokenError, TokenVerifier, VerifiedToken};
+
 /// Manages authentication for the IoT client
 pub struct AuthManager {
-    key_pair: Ed25519KeyPair,
+    signer: RwLock<Arc<dyn Signer>>,
+    retired: RwLock<Option<RetiredKey>>,
+    key_store: Option<Box<dyn KeyStore>>,
     device_id: String,
+    token_format: TokenFormat,
+    audience: Option<String>,
+}
+
+/// Previous key kept valid during a rotation's grace period
+struct RetiredKey {
+    signer: Arc<dyn Signer>,
+    valid_until: Instant,
 }
 
 impl AuthManager {
     /// Create a new auth manager from the client configuration
+    ///
+    /// The key comes from the first configured source: key file, base64
+    /// string, environment variable, identity directory, or a fresh key.
+    /// A key file is decrypted when a passphrase is configured.
     pub fn new(config: &ClientConfig) -> Result<Self> {
-        let key_pair = if let Some(key_path) = &config.private_key_path {
-            // Load key from file
-            let key_bytes = fs::read(key_path)
-                .context("Failed to read private key file")?;
-            Ed25519KeyPair::from_pkcs8(&key_bytes)
-                .map_err(|_| anyhow::anyhow!("Invalid key format"))?
+        let passphrase = config.private_key_passphrase.clone().map(Passphrase::Value)
+            .or_else(|| config.private_key_passphrase_env.clone().map(Passphrase::Env));
+
+        let key_store: Box<dyn KeyStore> = if let Some(key_path) = &config.private_key_path {
+            match passphrase {
+                Some(passphrase) => Box::new(EncryptedFileKeyStore::new(key_path, passphrase)),
+                None => Box::new(FileKeyStore::new(key_path)),
+            }
         } else if let Some(key_base64) = &config.private_key_base64 {
-            // Decode base64 key
-            let key_bytes = general_purpose::STANDARD.decode(key_base64)
-                .context("Invalid base64 encoding for private key")?;
-            Ed25519KeyPair::from_pkcs8(&key_bytes)
-                .map_err(|_| anyhow::anyhow!("Invalid key format"))?
+            Box::new(Base64KeyStore::new(key_base64.as_str()))
+        } else if let Some(var) = &config.private_key_env {
+            Box::new(EnvKeyStore::new(var.as_str()))
+        } else if let Some(identity_dir) = &config.identity_dir {
+            Box::new(IdentityKeyStore::new(IdentityStore::new(identity_dir), config.device_id.clone()))
         } else {
-            // Generate new key
-            let rng = ring::rand::SystemRandom::new();
-            let pkcs8_bytes = signature::Ed25519KeyPair::generate_pkcs8(&rng)
-                .map_err(|_| anyhow::anyhow!("Failed to generate key pair"))?;
-            Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref())
-                .map_err(|_| anyhow::anyhow!("Failed to parse generated key"))?
+            Box::new(EphemeralKeyStore)
+        };
+
+        Self::with_key_store(key_store, config)
+    }
+
+    /// Create an auth manager with a key from a custom key store
+    pub fn from_key_store<K: KeyStore + 'static>(key_store: K, config: &ClientConfig) -> Result<Self> {
+        Self::with_key_store(Box::new(key_store), config)
+    }
+
+    fn with_key_store(key_store: Box<dyn KeyStore>, config: &ClientConfig) -> Result<Self> {
+        let signer = key_store.load_signer()?;
+
+        let device_id = match config.device_id.clone() {
+            Some(device_id) => device_id,
+            None => key_store.device_id()?.unwrap_or_else(generate_device_id),
         };
 
-        let device_id = config.device_id.clone()
-            .unwrap_or_else(|| generate_device_id());
+        let mut manager = Self::with_signer(signer, device_id, config);
+        manager.key_store = Some(key_store);
+        Ok(manager)
+    }
 
-        Ok(Self {
-            key_pair,
+    /// Create an auth manager around an existing signer
+    ///
+    /// Without a key store, rotated keys are only kept in memory.
+    pub fn with_signer(signer: Arc<dyn Signer>, device_id: String, config: &ClientConfig) -> Self {
+        Self {
+            signer: RwLock::new(signer),
+            retired: RwLock::new(None),
+            key_store: None,
             device_id,
-        })
+            token_format: config.token_format,
+            audience: config.token_audience.clone(),
+        }
     }
 
     /// Get the device ID
@@ -50,8 +112,8 @@ impl AuthManager {
     }
 
     /// Get the public key for this device
-    pub fn public_key(&self) -> &[u8] {
-        self.key_pair.public_key().as_ref()
+    pub fn public_key(&self) -> Vec<u8> {
+        self.signer().public_key().to_vec()
     }
 
     /// Export the public key as base64
@@ -59,24 +121,154 @@ impl AuthManager {
         general_purpose::STANDARD.encode(self.public_key())
     }
 
+    /// Export the public key as a SubjectPublicKeyInfo PEM block
+    pub fn public_key_pem(&self) -> Result<String> {
+        Ok(PublicKey::from_bytes(&self.public_key())?.to_pem())
+    }
+
+    /// Export the public key as a JWK
+    pub fn public_key_jwk(&self) -> Result<serde_json::Value> {
+        Ok(PublicKey::from_bytes(&self.public_key())?.to_jwk())
+    }
+
+    /// Key ID of the public key, as used in the `kid` of JWT tokens
+    pub fn key_id(&self) -> String {
+        jwt::key_id(&self.public_key())
+    }
+
+    /// Format of the tokens minted by [`AuthManager::create_auth_token`]
+    pub fn token_format(&self) -> TokenFormat {
+        self.token_format
+    }
+
+    /// Signer holding the device's current private key
+    pub fn signer(&self) -> Arc<dyn Signer> {
+        self.signer.read().unwrap().clone()
+    }
+
     /// Sign a message with the device's private key
-    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
-        self.key_pair.sign(message).as_ref().to_vec()
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        self.signer().sign(message)
     }
 
     /// Verify a signature
+    ///
+    /// During a key rotation's grace period, signatures by the previous key
+    /// are accepted too.
     pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
-        let public_key = UnparsedPublicKey::new(&ED25519, self.public_key());
-        public_key.verify(message, signature)
-            .map_err(|_| anyhow::anyhow!("Signature verification failed"))
+        let mut keys = vec![self.public_key()];
+        keys.extend(self.previous_public_key());
+
+        keys.iter()
+            .any(|key| UnparsedPublicKey::new(&ED25519, key).verify(message, signature).is_ok())
+            .then_some(())
+            .ok_or_else(|| SdkError::Auth("Signature verification failed".to_string()))
+    }
+
+    /// Public key replaced by the last rotation, while its grace period lasts
+    pub fn previous_public_key(&self) -> Option<Vec<u8>> {
+        self.retired.read().unwrap().as_ref()
+            .filter(|retired| Instant::now() < retired.valid_until)
+            .map(|retired| retired.signer.public_key().to_vec())
+    }
+
+    /// Generate a new key and the request registering it with the server
+    ///
+    /// The request is signed by the current key, authorizing the rotation, and
+    /// by the new key, proving possession of it. Nothing changes locally until
+    /// [`AuthManager::complete_key_rotation`] is called.
+    pub fn begin_key_rotation(&self, grace_period: Duration) -> Result<KeyRotation> {
+        if let Some(key_store) = &self.key_store {
+            if !key_store.is_writable() {
+                return Err(SdkError::Key("Key store cannot persist a rotated key".to_string()));
+            }
+        }
+
+        let pkcs8 = keystore::generate_pkcs8()?;
+        let signer: Arc<dyn Signer> = Arc::new(SoftwareSigner::from_pkcs8(&pkcs8)?);
+
+        let mut request = KeyRotationRequest {
+            device_id: self.device_id.clone(),
+            public_key: general_purpose::STANDARD.encode(signer.public_key()),
+            key_id: jwt::key_id(signer.public_key()),
+            previous_key_id: self.key_id(),
+            issued_at: unix_now()?,
+            grace_period_secs: grace_period.as_secs(),
+            signature: String::new(),
+            proof: String::new(),
+        };
+
+        let input = request.signing_input();
+        request.signature = general_purpose::STANDARD.encode(self.sign(input.as_bytes())?);
+        request.proof = general_purpose::STANDARD.encode(signer.sign(input.as_bytes())?);
+
+        Ok(KeyRotation { pkcs8, signer, request, grace_period })
+    }
+
+    /// Persist the new key and switch to it
+    ///
+    /// Call once the server has accepted the rotation request. The stored key
+    /// is replaced atomically before the in-memory switch, so a crash leaves
+    /// either the old or the new key on disk, never neither.
+    pub fn complete_key_rotation(&self, rotation: KeyRotation) -> Result<()> {
+        let mut signer = self.signer.write().unwrap();
+
+        if jwt::key_id(signer.public_key()) != rotation.request.previous_key_id {
+            return Err(SdkError::Key("Key changed since the rotation began".to_string()));
+        }
+
+        // An error means the new key never replaced the stored one; once it
+        // has, save_key succeeds so disk and memory switch together
+        if let Some(key_store) = &self.key_store {
+            key_store.save_key(&self.device_id, &rotation.pkcs8)?;
+        }
+
+        let previous = std::mem::replace(&mut *signer, rotation.signer);
+        *self.retired.write().unwrap() = Some(RetiredKey {
+            signer: previous,
+            valid_until: Instant::now() + rotation.grace_period,
+        });
+
+        info!("Rotated device key to {}", rotation.request.key_id);
+        Ok(())
     }
 
     /// Create an authentication token for API requests
+    ///
+    /// The token format is chosen by `token_format` in the client configuration.
     pub fn create_auth_token(&self, expiration_seconds: u64) -> Result<String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .context("Failed to get current time")?
-            .as_secs();
+        match self.token_format {
+            TokenFormat::Legacy => self.create_legacy_token(expiration_seconds),
+            TokenFormat::Jwt => self.create_jwt(expiration_seconds),
+        }
+    }
+
+    /// Create a compact JWS token signed with EdDSA
+    pub fn create_jwt(&self, expiration_seconds: u64) -> Result<String> {
+        let now = unix_now()?;
+
+        let header = jwt::Header {
+            alg: jwt::ALGORITHM.to_string(),
+            typ: Some("JWT".to_string()),
+            kid: Some(self.key_id()),
+        };
+
+        let claims = Claims {
+            device_id: self.device_id.clone(),
+            sub: Some(self.device_id.clone()),
+            iat: now,
+            exp: now + expiration_seconds,
+            nbf: Some(now),
+            aud: self.audience.clone(),
+            jti: Some(generate_token_id()),
+        };
+
+        jwt::encode(&header, &claims, |input| self.sign(input))
+    }
+
+    /// Create a token in the legacy `base64(json.signature)` format
+    pub fn create_legacy_token(&self, expiration_seconds: u64) -> Result<String> {
+        let now = unix_now()?;
 
         let expiration = now + expiration_seconds;
 
@@ -87,7 +279,7 @@ impl AuthManager {
         });
 
         let payload_str = serde_json::to_string(&payload)?;
-        let signature = self.sign(payload_str.as_bytes());
+        let signature = self.sign(payload_str.as_bytes())?;
         let signature_base64 = general_purpose::STANDARD.encode(signature);
 
         let token = format!("{}.{}", payload_str, signature_base64);
@@ -95,10 +287,52 @@ impl AuthManager {
     }
 }
 
+/// Current time in seconds since the UNIX epoch
+fn unix_now() -> Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| SdkError::Auth("System clock is before the UNIX epoch".to_string()))?
+        .as_secs())
+}
+
+/// Generate a random token ID for the `jti` claim
+fn generate_token_id() -> String {
+    let mut rng = OsRng{};
+    let mut bytes = [0u8; 16];
+    rng.try_fill_bytes(&mut bytes).expect("Failed to generate random bytes");
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
 /// Generate a random device ID
-fn generate_device_id() -> String {
+pub(crate) fn generate_device_id() -> String {
     let mut rng = OsRng{};
     let mut bytes = [0u8; 32];
     rng.try_fill_bytes(&mut bytes).expect("Failed to generate random bytes");
     hex::encode(bytes)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::identity::tests::in_temp_dir;
+
+    #[test]
+    fn key_rotation_with_bare_key_file_name() {
+        in_temp_dir("key-rotation", || {
+            keys::generate_keypair("device.pem", KeyFormat::Pem).unwrap();
+            let config = ClientConfig::new("https://example.com")
+                .with_private_key_file("device.pem")
+                .with_device_id("device-1");
+            let auth = AuthManager::new(&config).unwrap();
+            let old_key = auth.public_key();
+
+            let rotation = auth.begin_key_rotation(Duration::from_secs(60)).unwrap();
+            let new_key = rotation.signer.public_key().to_vec();
+            auth.complete_key_rotation(rotation).unwrap();
+
+            assert_eq!(auth.public_key(), new_key);
+            assert_eq!(auth.previous_public_key(), Some(old_key));
+            assert_eq!(AuthManager::new(&config).unwrap().public_key(), new_key);
+        });
+    }
+}
@@ -0,0 +1,106 @@
+//! Device key rotation
+//!
+//! Rotation happens in two steps: [`AuthManager::begin_key_rotation`] creates
+//! a new key and a [`KeyRotationRequest`] signed by both keys, which the device
+//! registers with the server; [`AuthManager::complete_key_rotation`] then
+//! persists the new key and switches to it, keeping the old key valid locally
+//! for a grace period.
+//!
+//! [`AuthManager::begin_key_rotation`]: crate::auth::AuthManager::begin_key_rotation
+//! [`AuthManager::complete_key_rotation`]: crate::auth::AuthManager::complete_key_rotation
+
+use base64::{engine::general_purpose, Engine as _};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::auth::jwt;
+use crate::auth::keystore::Signer;
+use crate::auth::verifier::TokenError;
+
+/// Registration of a new public key, sent to the server during rotation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRotationRequest {
+    /// Device rotating its key
+    pub device_id: String,
+
+    /// New raw public key, base64
+    pub public_key: String,
+
+    /// Key ID of the new key
+    pub key_id: String,
+
+    /// Key ID of the key being replaced
+    pub previous_key_id: String,
+
+    /// Creation time, in seconds since the UNIX epoch
+    pub issued_at: u64,
+
+    /// How long the previous key should stay valid, in seconds
+    pub grace_period_secs: u64,
+
+    /// Signature of [`KeyRotationRequest::signing_input`] by the previous key
+    pub signature: String,
+
+    /// Signature of [`KeyRotationRequest::signing_input`] by the new key,
+    /// proving possession of its private key
+    pub proof: String,
+}
+
+impl KeyRotationRequest {
+    /// Bytes covered by both signatures
+    pub fn signing_input(&self) -> String {
+        format!(
+            "iot-dash-key-rotation.{}.{}.{}.{}.{}",
+            self.device_id, self.public_key, self.previous_key_id, self.issued_at, self.grace_period_secs
+        )
+    }
+
+    /// Check both signatures, given the device's currently registered key
+    ///
+    /// For servers handling rotation requests.
+    pub fn verify(&self, previous_public_key: &[u8]) -> std::result::Result<(), TokenError> {
+        let public_key = general_purpose::STANDARD.decode(&self.public_key)
+            .map_err(|_| TokenError::Malformed)?;
+
+        if jwt::key_id(&public_key) != self.key_id || jwt::key_id(previous_public_key) != self.previous_key_id {
+            return Err(TokenError::Malformed);
+        }
+
+        let input = self.signing_input();
+        for (key, signature) in [(previous_public_key, &self.signature), (public_key.as_slice(), &self.proof)] {
+            let signature = general_purpose::STANDARD.decode(signature)
+                .map_err(|_| TokenError::Malformed)?;
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(input.as_bytes(), &signature)
+                .map_err(|_| TokenError::InvalidSignature)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A new key waiting to be registered with the server and activated
+pub struct KeyRotation {
+    pub(crate) pkcs8: Vec<u8>,
+    pub(crate) signer: Arc<dyn Signer>,
+    pub(crate) request: KeyRotationRequest,
+    pub(crate) grace_period: Duration,
+}
+
+impl KeyRotation {
+    /// Request to send to the server
+    pub fn request(&self) -> &KeyRotationRequest {
+        &self.request
+    }
+
+    /// Key ID of the new key
+    pub fn key_id(&self) -> &str {
+        &self.request.key_id
+    }
+
+    /// How long the previous key stays valid after activation
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+}
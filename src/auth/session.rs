@@ -0,0 +1,187 @@
+//! Challenge-response login for server-issued session tokens
+//!
+//! Instead of minting its own tokens, the device asks the server for a
+//! nonce, signs it and exchanges the signature for a session token. The
+//! server controls the token lifetime and each nonce is usable once, so
+//! tokens can't be replayed and device clock drift doesn't matter.
+
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::Client;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+use crate::auth::AuthManager;
+use crate::communication::breaker::CircuitBreaker;
+use crate::communication::http::error_from_response;
+use crate::communication::retry::RetryPolicy;
+use crate::config::ClientConfig;
+use crate::error::Result;
+
+/// Endpoint issuing login nonces
+const CHALLENGE_PATH: &str = "/auth/challenge";
+
+/// Endpoint exchanging a signed nonce for a session token
+const SESSION_PATH: &str = "/auth/session";
+
+/// How the client obtains the tokens it sends to the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFlow {
+    /// Tokens minted and signed by the device itself
+    #[default]
+    SelfIssued,
+
+    /// Session tokens issued by the server after a challenge-response login
+    ChallengeResponse,
+}
+
+/// Nonce issued by the server for a login attempt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    /// Single-use nonce to sign
+    pub nonce: String,
+}
+
+/// Session token issued by the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionToken {
+    /// Bearer token for HTTP and WebSocket requests
+    pub token: String,
+
+    /// Seconds until the token expires
+    pub expires_in: u64,
+}
+
+impl SessionToken {
+    /// Lifetime of the token
+    pub fn lifetime(&self) -> Duration {
+        Duration::from_secs(self.expires_in)
+    }
+}
+
+/// Bytes the device signs to answer a challenge
+///
+/// The nonce is prefixed with a fixed label and the device ID so a server
+/// can't get the device to sign anything that doubles as a token.
+pub fn challenge_signing_input(device_id: &str, nonce: &str) -> String {
+    format!("iot-dash-login.{}.{}", device_id, nonce)
+}
+
+/// Performs the challenge-response login against the service API
+///
+/// Failed logins are retried as a whole, since a nonce is only usable once.
+/// Share the API's circuit breaker so an outage stops the login attempts too.
+#[derive(Clone)]
+pub struct SessionLogin {
+    client: Client,
+    base_url: String,
+    retry: RetryPolicy,
+    breaker: Option<Arc<CircuitBreaker>>,
+    config: Option<watch::Receiver<Arc<ClientConfig>>>,
+}
+
+impl SessionLogin {
+    /// Create a login client for the service at `base_url`
+    pub fn new(client: Client, base_url: &str) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            retry: RetryPolicy::default(),
+            breaker: None,
+            config: None,
+        }
+    }
+
+    /// Use the given retry policy instead of the default one
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Report login requests to a circuit breaker, e.g. the one of an
+    /// [`HttpClient`](crate::communication::HttpClient)
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Follow configuration updates, e.g. from a
+    /// [`ConfigWatcher`](crate::config::ConfigWatcher)
+    ///
+    /// Each login uses the latest `api_url`, like the API requests of an
+    /// [`HttpClient`](crate::communication::HttpClient) following the same updates.
+    pub fn with_config_updates(mut self, updates: watch::Receiver<Arc<ClientConfig>>) -> Self {
+        self.config = Some(updates);
+        self
+    }
+
+    /// Log in and return a new session token, retrying transient failures
+    pub async fn login(&self, auth_manager: &AuthManager) -> Result<SessionToken> {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            let error = match self.login_once(auth_manager).await {
+                Ok(session) => return Ok(session),
+                Err(e) => e,
+            };
+            attempt += 1;
+
+            let Some(delay) = self.retry.next_delay(attempt, &error, started.elapsed()) else {
+                return Err(error);
+            };
+
+            warn!("Login failed (attempt {}/{}), retrying in {:?}: {}", attempt, self.retry.max_attempts, delay, error);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn login_once(&self, auth_manager: &AuthManager) -> Result<SessionToken> {
+        let device_id = auth_manager.device_id();
+
+        let challenge: Challenge = self.post(CHALLENGE_PATH, &serde_json::json!({
+            "device_id": device_id,
+        })).await?;
+        debug!("Received login challenge for device {}", device_id);
+
+        let signature = auth_manager.sign(challenge_signing_input(device_id, &challenge.nonce).as_bytes())?;
+
+        let session: SessionToken = self.post(SESSION_PATH, &serde_json::json!({
+            "device_id": device_id,
+            "nonce": challenge.nonce,
+            "signature": general_purpose::STANDARD.encode(signature),
+            "key_id": auth_manager.key_id(),
+        })).await?;
+        info!("Device {} logged in, session valid for {}s", device_id, session.expires_in);
+
+        Ok(session)
+    }
+
+    async fn post<T: DeserializeOwned, B: Serialize>(&self, path: &str, body: &B) -> Result<T> {
+        let permit = self.breaker.as_ref().map(|breaker| breaker.acquire()).transpose()?;
+        let result = self.post_once(path, body).await;
+        if let Some(permit) = permit {
+            permit.record(&result);
+        }
+        result
+    }
+
+    async fn post_once<T: DeserializeOwned, B: Serialize>(&self, path: &str, body: &B) -> Result<T> {
+        let url = match &self.config {
+            Some(config) => format!("{}{}", config.borrow().api_url.trim_end_matches('/'), path),
+            None => format!("{}{}", self.base_url, path),
+        };
+
+        let response = self.client.post(url)
+            .json(body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(error_from_response(response).await);
+        }
+
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+}
@@ -0,0 +1,411 @@
+//! Verification of device tokens for dashboard backends
+
+use base64::{engine::general_purpose, Engine as _};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+use thiserror::Error;
+use crate::auth::jwt::{self, Claims, TokenFormat};
+use crate::error::{Result, SdkError};
+
+/// Reasons a token is rejected
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// The token is neither a valid JWT nor a valid legacy token
+    #[error("Malformed token")]
+    Malformed,
+
+    /// The JWT header names an algorithm other than EdDSA
+    #[error("Unsupported algorithm {0}")]
+    UnsupportedAlgorithm(String),
+
+    /// No public key is registered for the device
+    #[error("Unknown device {0}")]
+    UnknownDevice(String),
+
+    /// The signature does not match the device's public key
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    /// The token's `exp` is in the past
+    #[error("Token expired")]
+    Expired,
+
+    /// The token's `iat` or `nbf` is in the future
+    #[error("Token not yet valid")]
+    NotYetValid,
+
+    /// The token's `aud` does not match the expected audience
+    #[error("Invalid audience")]
+    InvalidAudience,
+}
+
+/// Source of device public keys for token verification
+pub trait PublicKeyResolver: Send + Sync {
+    /// Raw Ed25519 public key of a device, if it is known
+    ///
+    /// `key_id` is the `kid` from the JWT header, when present, so
+    /// implementations holding several keys per device can pick the right one.
+    fn public_key(&self, device_id: &str, key_id: Option<&str>) -> Option<Vec<u8>>;
+}
+
+impl PublicKeyResolver for HashMap<String, Vec<u8>> {
+    fn public_key(&self, device_id: &str, _key_id: Option<&str>) -> Option<Vec<u8>> {
+        self.get(device_id).cloned()
+    }
+}
+
+/// In-memory registry of device public keys
+#[derive(Default)]
+pub struct KeyRegistry {
+    keys: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl KeyRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register or replace a device's raw public key
+    pub fn insert(&self, device_id: &str, public_key: Vec<u8>) {
+        self.keys.write().unwrap().insert(device_id.to_string(), public_key);
+    }
+
+    /// Register or replace a device's base64-encoded public key
+    pub fn insert_base64(&self, device_id: &str, public_key: &str) -> Result<()> {
+        let key = general_purpose::STANDARD.decode(public_key)
+            .map_err(|_| SdkError::Key("Invalid base64 encoding for public key".to_string()))?;
+        self.insert(device_id, key);
+        Ok(())
+    }
+
+    /// Forget a device's public key
+    pub fn remove(&self, device_id: &str) -> bool {
+        self.keys.write().unwrap().remove(device_id).is_some()
+    }
+}
+
+impl PublicKeyResolver for KeyRegistry {
+    fn public_key(&self, device_id: &str, _key_id: Option<&str>) -> Option<Vec<u8>> {
+        self.keys.read().unwrap().get(device_id).cloned()
+    }
+}
+
+/// A token that passed verification
+#[derive(Debug, Clone)]
+pub struct VerifiedToken {
+    /// Claims carried by the token
+    pub claims: Claims,
+
+    /// Format the token was in
+    pub format: TokenFormat,
+}
+
+impl VerifiedToken {
+    /// Device the token was issued for
+    pub fn device_id(&self) -> &str {
+        &self.claims.device_id
+    }
+}
+
+/// Verifies tokens minted by [`AuthManager`](crate::auth::AuthManager)
+///
+/// Accepts both JWT and legacy tokens, checks the Ed25519 signature against
+/// the device's registered key and enforces `exp`, `iat` and `nbf` with a
+/// clock-skew leeway.
+pub struct TokenVerifier<R: PublicKeyResolver> {
+    resolver: R,
+    leeway: Duration,
+    audience: Option<String>,
+}
+
+impl<R: PublicKeyResolver> TokenVerifier<R> {
+    /// Create a verifier with a 60 second leeway and no audience check
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            leeway: Duration::from_secs(60),
+            audience: None,
+        }
+    }
+
+    /// Set the allowed clock skew between device and server
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Require tokens to carry this `aud` claim
+    pub fn with_audience<S: Into<String>>(mut self, audience: S) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// Get the key resolver
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+
+    /// Verify a token and return its claims
+    pub fn verify(&self, token: &str) -> std::result::Result<VerifiedToken, TokenError> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.verify_at(token, now)
+    }
+
+    /// Verify a token as of the given UNIX time
+    pub fn verify_at(&self, token: &str, now: u64) -> std::result::Result<VerifiedToken, TokenError> {
+        let verified = match jwt::decode(token) {
+            Some((header, claims, signing_input, signature)) => {
+                if header.alg != jwt::ALGORITHM {
+                    return Err(TokenError::UnsupportedAlgorithm(header.alg));
+                }
+                if claims.sub.as_ref().is_some_and(|sub| *sub != claims.device_id) {
+                    return Err(TokenError::Malformed);
+                }
+                self.check_signature(&claims.device_id, header.kid.as_deref(), signing_input.as_bytes(), &signature)?;
+                VerifiedToken { claims, format: TokenFormat::Jwt }
+            },
+            None => {
+                let (claims, payload, signature) = decode_legacy(token).ok_or(TokenError::Malformed)?;
+                self.check_signature(&claims.device_id, None, payload.as_bytes(), &signature)?;
+                VerifiedToken { claims, format: TokenFormat::Legacy }
+            }
+        };
+
+        self.check_times(&verified.claims, now)?;
+
+        if let Some(expected) = &self.audience {
+            if verified.claims.aud.as_ref() != Some(expected) {
+                return Err(TokenError::InvalidAudience);
+            }
+        }
+
+        Ok(verified)
+    }
+
+    fn check_signature(&self, device_id: &str, key_id: Option<&str>, message: &[u8], signature: &[u8])
+        -> std::result::Result<(), TokenError> {
+        let public_key = self.resolver.public_key(device_id, key_id)
+            .ok_or_else(|| TokenError::UnknownDevice(device_id.to_string()))?;
+
+        UnparsedPublicKey::new(&ED25519, &public_key)
+            .verify(message, signature)
+            .map_err(|_| TokenError::InvalidSignature)
+    }
+
+    fn check_times(&self, claims: &Claims, now: u64) -> std::result::Result<(), TokenError> {
+        let leeway = self.leeway.as_secs();
+
+        if now > claims.exp.saturating_add(leeway) {
+            return Err(TokenError::Expired);
+        }
+
+        let not_before = claims.nbf.unwrap_or(claims.iat).max(claims.iat);
+        if not_before > now.saturating_add(leeway) {
+            return Err(TokenError::NotYetValid);
+        }
+
+        Ok(())
+    }
+}
+
+/// Split a legacy token into its claims, signed payload and signature
+fn decode_legacy(token: &str) -> Option<(Claims, String, Vec<u8>)> {
+    let decoded = general_purpose::STANDARD.decode(token).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+
+    // The signature is base64 and never contains '.', the JSON payload might
+    let (payload, signature_b64) = decoded.rsplit_once('.')?;
+    let signature = general_purpose::STANDARD.decode(signature_b64).ok()?;
+    let claims: Claims = serde_json::from_str(payload).ok()?;
+
+    Some((claims, payload.to_string(), signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::auth::keystore::{Signer, SoftwareSigner};
+
+    const DEVICE: &str = "device-1";
+    const NOW: u64 = 1_700_000_000;
+
+    fn claims(iat: u64, exp: u64) -> Claims {
+        Claims {
+            device_id: DEVICE.to_string(),
+            sub: Some(DEVICE.to_string()),
+            iat,
+            exp,
+            nbf: Some(iat),
+            aud: None,
+            jti: None,
+        }
+    }
+
+    fn jwt(signer: &SoftwareSigner, claims: &Claims) -> String {
+        let header = jwt::Header {
+            alg: jwt::ALGORITHM.to_string(),
+            typ: Some("JWT".to_string()),
+            kid: Some(jwt::key_id(signer.public_key())),
+        };
+        jwt::encode(&header, claims, |input| signer.sign(input)).unwrap()
+    }
+
+    fn legacy(signer: &SoftwareSigner, iat: u64, exp: u64) -> String {
+        let payload = serde_json::json!({ "device_id": DEVICE, "exp": exp, "iat": iat }).to_string();
+        let signature = general_purpose::STANDARD.encode(signer.sign(payload.as_bytes()).unwrap());
+        general_purpose::STANDARD.encode(format!("{}.{}", payload, signature))
+    }
+
+    fn verifier(signer: &SoftwareSigner) -> TokenVerifier<KeyRegistry> {
+        let registry = KeyRegistry::new();
+        registry.insert(DEVICE, signer.public_key().to_vec());
+        TokenVerifier::new(registry).with_leeway(Duration::from_secs(60))
+    }
+
+    /// Resolver holding one key per device, looked up by `kid`
+    struct KidResolver(Vec<u8>);
+
+    impl PublicKeyResolver for KidResolver {
+        fn public_key(&self, device_id: &str, key_id: Option<&str>) -> Option<Vec<u8>> {
+            (device_id == DEVICE && key_id == Some(jwt::key_id(&self.0).as_str())).then(|| self.0.clone())
+        }
+    }
+
+    #[test]
+    fn accepts_valid_jwt() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verified = verifier(&signer).verify_at(&jwt(&signer, &claims(NOW, NOW + 300)), NOW).unwrap();
+        assert_eq!(verified.format, TokenFormat::Jwt);
+        assert_eq!(verified.device_id(), DEVICE);
+    }
+
+    #[test]
+    fn accepts_valid_legacy_token() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verified = verifier(&signer).verify_at(&legacy(&signer, NOW, NOW + 300), NOW).unwrap();
+        assert_eq!(verified.format, TokenFormat::Legacy);
+        assert_eq!(verified.claims.exp, NOW + 300);
+    }
+
+    #[test]
+    fn accepts_tokens_from_auth_manager() {
+        let signer = Arc::new(SoftwareSigner::generate().unwrap());
+        let config = crate::config::ClientConfig::new("https://example.com");
+        let auth = crate::auth::AuthManager::with_signer(signer.clone(), DEVICE.to_string(), &config);
+        let verifier = verifier(&signer);
+
+        assert!(verifier.verify(&auth.create_jwt(300).unwrap()).is_ok());
+        assert!(verifier.verify(&auth.create_legacy_token(300).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn rejects_tampered_signature() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verifier = verifier(&signer);
+
+        let token = jwt(&signer, &claims(NOW, NOW + 300));
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let mut signature = general_purpose::URL_SAFE_NO_PAD.decode(signature).unwrap();
+        signature[0] ^= 1;
+        let tampered = format!("{}.{}", signing_input, general_purpose::URL_SAFE_NO_PAD.encode(signature));
+        assert_eq!(verifier.verify_at(&tampered, NOW).unwrap_err(), TokenError::InvalidSignature);
+
+        // Claims swapped in from another token keep the original signature
+        let other = jwt(&signer, &claims(NOW, NOW + 3600));
+        let mut parts: Vec<&str> = token.split('.').collect();
+        parts[1] = other.split('.').nth(1).unwrap();
+        assert_eq!(verifier.verify_at(&parts.join("."), NOW).unwrap_err(), TokenError::InvalidSignature);
+
+        let other_signer = SoftwareSigner::generate().unwrap();
+        assert_eq!(
+            verifier.verify_at(&legacy(&other_signer, NOW, NOW + 300), NOW).unwrap_err(),
+            TokenError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn expiry_honors_leeway() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verifier = verifier(&signer);
+        let exp = NOW + 300;
+
+        for token in [jwt(&signer, &claims(NOW, exp)), legacy(&signer, NOW, exp)] {
+            assert!(verifier.verify_at(&token, exp).is_ok());
+            assert!(verifier.verify_at(&token, exp + 60).is_ok());
+            assert_eq!(verifier.verify_at(&token, exp + 61).unwrap_err(), TokenError::Expired);
+        }
+    }
+
+    #[test]
+    fn rejects_not_before_in_the_future() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verifier = verifier(&signer);
+
+        let mut future = claims(NOW, NOW + 600);
+        future.nbf = Some(NOW + 120);
+        let token = jwt(&signer, &future);
+        assert_eq!(verifier.verify_at(&token, NOW).unwrap_err(), TokenError::NotYetValid);
+        assert!(verifier.verify_at(&token, NOW + 60).is_ok());
+
+        // iat in the future counts even without nbf
+        let mut issued_later = claims(NOW + 120, NOW + 600);
+        issued_later.nbf = None;
+        assert_eq!(verifier.verify_at(&jwt(&signer, &issued_later), NOW).unwrap_err(), TokenError::NotYetValid);
+    }
+
+    #[test]
+    fn checks_audience() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verifier = verifier(&signer).with_audience("dashboard");
+
+        let mut claims = claims(NOW, NOW + 300);
+        claims.aud = Some("dashboard".to_string());
+        assert!(verifier.verify_at(&jwt(&signer, &claims), NOW).is_ok());
+
+        claims.aud = Some("other".to_string());
+        assert_eq!(verifier.verify_at(&jwt(&signer, &claims), NOW).unwrap_err(), TokenError::InvalidAudience);
+
+        claims.aud = None;
+        assert_eq!(verifier.verify_at(&jwt(&signer, &claims), NOW).unwrap_err(), TokenError::InvalidAudience);
+        assert_eq!(
+            verifier.verify_at(&legacy(&signer, NOW, NOW + 300), NOW).unwrap_err(),
+            TokenError::InvalidAudience
+        );
+    }
+
+    #[test]
+    fn looks_up_keys_by_kid() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verifier = TokenVerifier::new(KidResolver(signer.public_key().to_vec()));
+        let claims = claims(NOW, NOW + 300);
+
+        assert!(verifier.verify_at(&jwt(&signer, &claims), NOW).is_ok());
+
+        let header = jwt::Header {
+            alg: jwt::ALGORITHM.to_string(),
+            typ: None,
+            kid: Some("unknown-kid".to_string()),
+        };
+        let token = jwt::encode(&header, &claims, |input| signer.sign(input)).unwrap();
+        assert_eq!(verifier.verify_at(&token, NOW).unwrap_err(), TokenError::UnknownDevice(DEVICE.to_string()));
+    }
+
+    #[test]
+    fn rejects_unknown_device_and_malformed_tokens() {
+        let signer = SoftwareSigner::generate().unwrap();
+        let verifier = TokenVerifier::new(KeyRegistry::new());
+        assert_eq!(
+            verifier.verify_at(&jwt(&signer, &claims(NOW, NOW + 300)), NOW).unwrap_err(),
+            TokenError::UnknownDevice(DEVICE.to_string())
+        );
+        assert_eq!(verifier.verify_at("not a token", NOW).unwrap_err(), TokenError::Malformed);
+    }
+}
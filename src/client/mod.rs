@@ -0,0 +1,187 @@
+//! High-level client that ties auth, HTTP, WebSocket and webhooks together
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::info;
+use crate::auth::cache::{DEFAULT_REFRESH_FRACTION, DEFAULT_TOKEN_LIFETIME};
+use crate::auth::{AuthFlow, AuthManager, SessionLogin, TokenCache};
+use crate::communication::{CircuitState, HttpClient, OfflineQueue, ReconnectPolicy, TlsConfig, WebSocketConnection};
+use crate::config::{ClientConfig, ConfigWatcher};
+use crate::device::DeviceManager;
+use crate::error::{Result, SdkError};
+use crate::webhooks::WebhookManager;
+
+/// Entry point for talking to the IoT dashboard
+///
+/// Owns a single [`AuthManager`] and [`TokenCache`] shared by every HTTP
+/// handle and by the WebSocket connection.
+pub struct IotClient {
+    config: ClientConfig,
+    auth_manager: Arc<AuthManager>,
+    tokens: Arc<TokenCache>,
+    http_client: HttpClient,
+    devices: DeviceManager,
+    webhooks: WebhookManager,
+    realtime: WebSocketConnection,
+}
+
+impl IotClient {
+    /// Create a new client from the given configuration
+    ///
+    /// The configuration is normalized and validated first; see
+    /// [`ClientConfig::validate`].
+    pub fn new(config: ClientConfig) -> Result<Self> {
+        Self::build(config, None)
+    }
+
+    /// Create a client that follows a [`ConfigWatcher`]
+    ///
+    /// HTTP requests pick up a new `api_url` or `request_timeout` right away,
+    /// session logins follow `api_url` as well, and a supervised WebSocket
+    /// reconnects when `websocket_url` changes.
+    /// Other settings, such as keys and TLS, apply to new clients only. The
+    /// server can trigger a reload with the
+    /// [`RELOAD_COMMAND`](crate::config::watcher::RELOAD_COMMAND) command.
+    pub fn with_config_watcher(watcher: &ConfigWatcher) -> Result<Self> {
+        let client = Self::build((*watcher.current()).clone(), Some(watcher.subscribe()))?;
+        watcher.register_commands(client.realtime.commands());
+        Ok(client)
+    }
+
+    fn build(config: ClientConfig, updates: Option<watch::Receiver<Arc<ClientConfig>>>) -> Result<Self> {
+        let config = config.validated()?;
+        let auth_manager = Arc::new(AuthManager::new(&config)?);
+
+        let tls = config.tls.as_ref().map(TlsConfig::build).transpose()?;
+
+        let mut http_client = HttpClient::new_with_tls(
+            &config.api_url,
+            auth_manager.clone(),
+            config.request_timeout.unwrap_or(30),
+            tls.clone(),
+        )?;
+
+        if let Some(retry) = &config.http_retry {
+            http_client = http_client.with_retry_policy(retry.clone());
+        }
+        if let Some(breaker) = &config.circuit_breaker {
+            http_client = http_client.with_circuit_breaker(breaker.clone());
+        }
+        if let Some(updates) = &updates {
+            http_client = http_client.with_config_updates(updates.clone());
+        }
+
+        let mut tokens = TokenCache::new(
+            auth_manager.clone(),
+            config.token_lifetime.map(Duration::from_secs).unwrap_or(DEFAULT_TOKEN_LIFETIME),
+            config.token_refresh_fraction.unwrap_or(DEFAULT_REFRESH_FRACTION),
+        );
+        if config.auth_flow == AuthFlow::ChallengeResponse {
+            let mut login = SessionLogin::new(http_client.reqwest_client().clone(), &config.api_url)
+                .with_retry_policy(http_client.retry_policy().clone())
+                .with_circuit_breaker(http_client.circuit_breaker().clone());
+            if let Some(updates) = &updates {
+                login = login.with_config_updates(updates.clone());
+            }
+            tokens = tokens.with_session_login(login);
+        }
+        let tokens = Arc::new(tokens);
+
+        let http_client = http_client.with_token_cache(tokens.clone());
+
+        let devices = DeviceManager::new(http_client.clone());
+        let webhooks = WebhookManager::new(http_client.clone());
+
+        let mut realtime = WebSocketConnection::new();
+        realtime.set_auth_mode(config.websocket_auth);
+        if let Some(tls) = tls {
+            realtime.set_tls(tls);
+        }
+        if let Some(updates) = updates {
+            realtime.set_config_updates(updates);
+        }
+        if let Some(queue_config) = &config.offline_queue {
+            realtime.set_offline_queue(OfflineQueue::open(queue_config.clone())?);
+        }
+
+        info!("IoT client initialized for device {}", auth_manager.device_id());
+
+        Ok(Self {
+            config,
+            auth_manager,
+            tokens,
+            http_client,
+            devices,
+            webhooks,
+            realtime,
+        })
+    }
+
+    /// Get the configuration this client was built from
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Get the shared auth manager
+    pub fn auth(&self) -> &Arc<AuthManager> {
+        &self.auth_manager
+    }
+
+    /// Get the token cache shared by HTTP requests and the WebSocket
+    pub fn tokens(&self) -> &Arc<TokenCache> {
+        &self.tokens
+    }
+
+    /// State of the circuit breaker guarding API requests, for health reporting
+    pub fn api_circuit_state(&self) -> CircuitState {
+        self.http_client.circuit_breaker().state()
+    }
+
+    /// Get the device ID this client authenticates as
+    pub fn device_id(&self) -> &str {
+        self.auth_manager.device_id()
+    }
+
+    /// Get the raw HTTP client for endpoints not covered by a manager
+    pub fn http(&self) -> &HttpClient {
+        &self.http_client
+    }
+
+    /// Get the device manager
+    pub fn devices(&self) -> &DeviceManager {
+        &self.devices
+    }
+
+    /// Get the webhook manager
+    pub fn webhooks(&self) -> &WebhookManager {
+        &self.webhooks
+    }
+
+    /// Get the real-time WebSocket connection
+    pub fn realtime(&self) -> &WebSocketConnection {
+        &self.realtime
+    }
+
+    /// Get the real-time WebSocket connection for configuration
+    pub fn realtime_mut(&mut self) -> &mut WebSocketConnection {
+        &mut self.realtime
+    }
+
+    /// Start the real-time WebSocket with default reconnection settings
+    ///
+    /// The connection is supervised: it mints a fresh token and reconnects
+    /// on its own whenever the socket drops.
+    pub fn connect_realtime(&mut self) -> Result<()> {
+        self.connect_realtime_with(ReconnectPolicy::default())
+    }
+
+    /// Start the real-time WebSocket with the given reconnection settings
+    pub fn connect_realtime_with(&mut self, policy: ReconnectPolicy) -> Result<()> {
+        let url = self.config.websocket_url.as_deref()
+            .ok_or_else(|| SdkError::Config("WebSocket URL is not configured".to_string()))?;
+
+        self.realtime.start_supervised(url, self.tokens.clone(), policy);
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+//! Circuit breaker for requests to the dashboard API
+//!
+//! When too many recent requests failed, the breaker opens and requests fail
+//! fast with [`SdkError::CircuitOpen`] instead of reaching the API. After a
+//! cooldown a few probe requests are let through; if they succeed the breaker
+//! closes again, otherwise it stays open for another cooldown.
+
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use crate::error::{Result, SdkError};
+
+/// Settings of a [`CircuitBreaker`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Whether the breaker is active; when disabled it never opens
+    pub enabled: bool,
+
+    /// Fraction of failed requests in the window that opens the breaker (0.0 to 1.0)
+    pub failure_threshold: f64,
+
+    /// Requests needed in the window before the failure rate is considered
+    pub minimum_requests: usize,
+
+    /// Length of the sliding window of recent requests, in milliseconds
+    pub window_ms: u64,
+
+    /// How long the breaker stays open before probing the API, in milliseconds
+    pub cooldown_ms: u64,
+
+    /// Probe requests allowed at once while half-open
+    pub half_open_max_requests: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failure_threshold: 0.5,
+            minimum_requests: 10,
+            window_ms: 60_000,
+            cooldown_ms: 30_000,
+            half_open_max_requests: 1,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    /// A breaker that never opens
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// State of a [`CircuitBreaker`], e.g. for health reporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests go through and their outcomes are tracked
+    Closed,
+
+    /// Requests fail fast until the cooldown has passed
+    Open,
+
+    /// A limited number of probe requests decide whether to close again
+    HalfOpen,
+}
+
+/// Tracks request outcomes and stops requests while the API is failing
+///
+/// Connection errors, timeouts, `429` and `5xx` responses count as
+/// failures; other responses show the API is up and count as successes.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+enum Inner {
+    Closed { outcomes: VecDeque<(Instant, bool)> },
+    Open { until: Instant },
+    HalfOpen { in_flight: usize },
+}
+
+impl CircuitBreaker {
+    /// Create a closed breaker
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Inner::Closed { outcomes: VecDeque::new() }),
+        }
+    }
+
+    /// Get the breaker's settings
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    /// Current state
+    ///
+    /// An open breaker whose cooldown has passed is reported as half-open.
+    pub fn state(&self) -> CircuitState {
+        match &*self.inner.lock().unwrap() {
+            Inner::Closed { .. } => CircuitState::Closed,
+            Inner::Open { until } if Instant::now() < *until => CircuitState::Open,
+            Inner::Open { .. } | Inner::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Close the breaker and forget recent outcomes
+    pub fn reset(&self) {
+        *self.inner.lock().unwrap() = Inner::Closed { outcomes: VecDeque::new() };
+    }
+
+    /// Ask to send a request
+    ///
+    /// Fails with [`SdkError::CircuitOpen`] while the breaker is open or all
+    /// half-open probes are in flight.
+    pub(crate) fn acquire(&self) -> Result<CircuitPermit<'_>> {
+        if !self.config.enabled {
+            return Ok(CircuitPermit { breaker: self, probe: false });
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        let probe = match &mut *inner {
+            Inner::Closed { .. } => false,
+            Inner::Open { until } if now < *until => {
+                return Err(SdkError::CircuitOpen { retry_after: *until - now });
+            },
+            Inner::Open { .. } => {
+                info!("Circuit breaker half-open, probing the API");
+                *inner = Inner::HalfOpen { in_flight: 1 };
+                true
+            },
+            Inner::HalfOpen { in_flight } if *in_flight < self.config.half_open_max_requests => {
+                *in_flight += 1;
+                true
+            },
+            Inner::HalfOpen { .. } => {
+                return Err(SdkError::CircuitOpen { retry_after: Duration::ZERO });
+            },
+        };
+
+        Ok(CircuitPermit { breaker: self, probe })
+    }
+
+    fn record(&self, success: bool, probe: bool) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        match &mut *inner {
+            Inner::Closed { outcomes } => {
+                outcomes.push_back((now, success));
+                let window = Duration::from_millis(self.config.window_ms);
+                while outcomes.front().is_some_and(|(at, _)| now.duration_since(*at) > window) {
+                    outcomes.pop_front();
+                }
+
+                let failures = outcomes.iter().filter(|(_, success)| !success).count();
+                let rate = failures as f64 / outcomes.len() as f64;
+                if outcomes.len() >= self.config.minimum_requests && rate >= self.config.failure_threshold {
+                    warn!(
+                        "Circuit breaker open: {} of the last {} requests failed",
+                        failures,
+                        outcomes.len()
+                    );
+                    *inner = self.open(now);
+                }
+            },
+            Inner::HalfOpen { .. } if probe && success => {
+                info!("Circuit breaker closed, the API is responding again");
+                *inner = Inner::Closed { outcomes: VecDeque::new() };
+            },
+            Inner::HalfOpen { .. } if probe => {
+                warn!("Circuit breaker probe failed, staying open");
+                *inner = self.open(now);
+            },
+            // Outcomes of requests started before the state changed
+            Inner::HalfOpen { .. } | Inner::Open { .. } => {},
+        }
+    }
+
+    /// Give back a half-open slot whose request never completed
+    fn release(&self) {
+        if let Inner::HalfOpen { in_flight } = &mut *self.inner.lock().unwrap() {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+
+    fn open(&self, now: Instant) -> Inner {
+        Inner::Open { until: now + Duration::from_millis(self.config.cooldown_ms) }
+    }
+}
+
+/// Permission to send one request; report its outcome with [`CircuitPermit::record`]
+///
+/// Dropping the permit without a result, e.g. when the request is
+/// cancelled, frees the probe slot without counting an outcome.
+pub(crate) struct CircuitPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl CircuitPermit<'_> {
+    /// Record the outcome of the request
+    pub(crate) fn record<T>(mut self, result: &Result<T>) {
+        let success = match result {
+            Ok(_) => true,
+            Err(e) => !e.is_retryable(),
+        };
+        self.breaker.record(success, self.probe);
+        self.probe = false;
+    }
+}
+
+impl Drop for CircuitPermit<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.release();
+        }
+    }
+}
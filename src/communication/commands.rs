@@ -0,0 +1,147 @@
+//! Registry of handlers for commands received over the WebSocket
+
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+type CommandFuture = Pin<Box<dyn Future<Output = CommandResponse> + Send>>;
+type Handler = Arc<dyn Fn(serde_json::Value) -> CommandFuture + Send + Sync>;
+
+/// Command carried in the payload of a `command` message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRequest {
+    /// Name the handler was registered under
+    pub command: String,
+
+    /// Parameters passed to the handler
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+/// Outcome of a command
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CommandStatus {
+    #[serde(rename = "success")]
+    Success,
+
+    #[serde(rename = "failure")]
+    Failure,
+}
+
+/// Result of a command, sent back as the payload of its acknowledgement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandResponse {
+    /// Whether the handler succeeded
+    pub status: CommandStatus,
+
+    /// Data returned by the handler on success
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+
+    /// Error message on failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CommandResponse {
+    /// Create a successful response
+    pub fn success(data: serde_json::Value) -> Self {
+        Self {
+            status: CommandStatus::Success,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    /// Create a failed response
+    pub fn failure<S: Into<String>>(error: S) -> Self {
+        Self {
+            status: CommandStatus::Failure,
+            data: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// Handlers for incoming commands, keyed by command name
+///
+/// Cloning is cheap and clones share the same handlers, so commands can be
+/// registered before or after the connection is established.
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    handlers: Arc<RwLock<HashMap<String, Handler>>>,
+}
+
+impl CommandRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an async handler for a command
+    ///
+    /// The command's `params` are deserialized into `P`. The handler's
+    /// `Ok` value is returned as the ack `data`, its `Err` as the ack `error`.
+    /// Registering a name twice replaces the previous handler.
+    pub fn register<P, R, E, F, Fut>(&self, name: &str, handler: F)
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize,
+        E: Display,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, E>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let wrapped: Handler = Arc::new(move |params: serde_json::Value| {
+            let handler = handler.clone();
+            Box::pin(async move {
+                let params = match serde_json::from_value::<P>(params) {
+                    Ok(params) => params,
+                    Err(e) => return CommandResponse::failure(format!("Invalid parameters: {}", e)),
+                };
+
+                match handler(params).await {
+                    Ok(data) => match serde_json::to_value(data) {
+                        Ok(value) => CommandResponse::success(value),
+                        Err(e) => CommandResponse::failure(format!("Failed to serialize result: {}", e)),
+                    },
+                    Err(e) => CommandResponse::failure(e.to_string()),
+                }
+            })
+        });
+
+        self.handlers.write().unwrap().insert(name.to_string(), wrapped);
+    }
+
+    /// Remove the handler for a command, returning whether one was registered
+    pub fn unregister(&self, name: &str) -> bool {
+        self.handlers.write().unwrap().remove(name).is_some()
+    }
+
+    /// Check if a handler is registered for a command
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.read().unwrap().contains_key(name)
+    }
+
+    /// Run the handler for a command
+    pub async fn dispatch(&self, request: CommandRequest) -> CommandResponse {
+        let handler = self.handlers.read().unwrap().get(&request.command).cloned();
+
+        let Some(handler) = handler else {
+            warn!("No handler registered for command {}", request.command);
+            return CommandResponse::failure(format!("Unknown command: {}", request.command));
+        };
+
+        info!("Running handler for command {}", request.command);
+
+        // Run on its own task so a panicking handler still produces a response
+        match tokio::spawn(handler(request.params)).await {
+            Ok(response) => response,
+            Err(_) => CommandResponse::failure("Command handler panicked"),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Delivery guarantees for messages sent over the WebSocket
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use crate::communication::websocket::WebSocketMessage;
+
+/// Retry settings for at-least-once delivery
+#[derive(Debug, Clone)]
+pub struct DeliveryPolicy {
+    /// How long to wait for the server's acknowledgement before resending
+    pub ack_timeout: Duration,
+
+    /// Total number of sends before giving up, including the first
+    pub max_attempts: usize,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_secs(10),
+            max_attempts: 3,
+        }
+    }
+}
+
+/// How data and status messages are delivered
+#[derive(Debug, Clone, Default)]
+pub enum DeliveryGuarantee {
+    /// Send once and return as soon as the message is queued
+    #[default]
+    AtMostOnce,
+
+    /// Wait for the server's acknowledgement and resend on timeout
+    ///
+    /// Resends reuse the message ID so the server can deduplicate them.
+    /// A message written to the offline queue counts as delivered once it is
+    /// durably stored; its ack after draining is not waited for.
+    AtLeastOnce(DeliveryPolicy),
+}
+
+/// Table of messages waiting for an acknowledgement, keyed by message ID
+#[derive(Clone, Default)]
+pub(crate) struct PendingAcks {
+    inner: Arc<Mutex<HashMap<String, oneshot::Sender<WebSocketMessage>>>>,
+}
+
+impl PendingAcks {
+    /// Start tracking a message, returning a receiver for its acknowledgement
+    pub(crate) fn register(&self, id: &str) -> oneshot::Receiver<WebSocketMessage> {
+        let (tx, rx) = oneshot::channel();
+        self.inner.lock().unwrap().insert(id.to_string(), tx);
+        rx
+    }
+
+    /// Stop tracking a message
+    pub(crate) fn remove(&self, id: &str) {
+        self.inner.lock().unwrap().remove(id);
+    }
+
+    /// Resolve the pending message matching an acknowledgement
+    ///
+    /// Returns `false` if no message with that ID is in flight.
+    pub(crate) fn complete(&self, ack: &WebSocketMessage) -> bool {
+        let Some(id) = &ack.id else {
+            return false;
+        };
+
+        match self.inner.lock().unwrap().remove(id) {
+            Some(tx) => {
+                let _ = tx.send(ack.clone());
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Number of messages waiting for an acknowledgement
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+}
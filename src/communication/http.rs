@@ -1,117 +1,368 @@
 //! HTTP client for communicating with the IoT service API
 
-use anyhow::{Result, Context};
-use reqwest::{Client, header};
+use crate::error::{ApiError, Result, SdkError};
+use base64::{engine::general_purpose, Engine as _};
+use rand::Rng;
+use reqwest::{Client, Method, StatusCode, header};
 use serde::{Serialize, de::DeserializeOwned};
-use std::time::Duration;
-use crate::auth::AuthManager;
-use tracing::{debug, error};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use crate::auth::cache::{DEFAULT_REFRESH_FRACTION, DEFAULT_TOKEN_LIFETIME};
+use crate::auth::{AuthManager, TokenCache};
+use crate::communication::breaker::{CircuitBreaker, CircuitBreakerConfig};
+use crate::communication::retry::RetryPolicy;
+use crate::config::ClientConfig;
+use tokio::sync::watch;
+use tracing::{debug, error, warn};
+
+/// Header carrying the key the server uses to deduplicate retried requests
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
 
 /// HTTP Client for the IoT service API
+///
+/// Cloning is cheap: the underlying connection pool, auth manager and token
+/// cache are shared.
+#[derive(Clone)]
 pub struct HttpClient{
     client: Client,
     base_url: String,
-    auth_manager:AuthManager,
+    auth_manager: Arc<AuthManager>,
+    tokens: Arc<TokenCache>,
+    config: Option<watch::Receiver<Arc<ClientConfig>>>,
+    retry: RetryPolicy,
+    idempotency_keys: bool,
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl HttpClient {
     /// Create a new HTTP Client
-    pub fn new(base_url: &str, auth_manager: AuthManager, timeout_seconds: u64) -> Result<Self>{
-        let client = Client::builder()
-            .timeout(Duration::from_secs(timeout_seconds))
-            .build()
-            .context("Failed to create HTTP Client")?;
+    pub fn new(base_url: &str, auth_manager: Arc<AuthManager>, timeout_seconds: u64) -> Result<Self>{
+        Self::new_with_tls(base_url, auth_manager, timeout_seconds, None)
+    }
+
+    /// Create a new HTTP Client using the given TLS settings instead of the defaults
+    pub fn new_with_tls(
+        base_url: &str,
+        auth_manager: Arc<AuthManager>,
+        timeout_seconds: u64,
+        tls: Option<Arc<rustls::ClientConfig>>,
+    ) -> Result<Self> {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(timeout_seconds));
+        if let Some(tls) = tls {
+            builder = builder.use_preconfigured_tls((*tls).clone());
+        }
+        let client = builder.build()?;
+
+        let tokens = Arc::new(TokenCache::new(
+            auth_manager.clone(),
+            DEFAULT_TOKEN_LIFETIME,
+            DEFAULT_REFRESH_FRACTION,
+        ));
 
         Ok(Self {
             client,
             base_url: base_url.to_string(),
             auth_manager,
+            tokens,
+            config: None,
+            retry: RetryPolicy::default(),
+            idempotency_keys: true,
+            breaker: Arc::new(CircuitBreaker::new(CircuitBreakerConfig::default())),
         })
     }
 
-    /// Make an authenticated GET request
-    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
-        let url = format!("{}{}", self.base_url, path);
-        let token = self.auth_manager.create_auth_token(300)?; // 5 minutes
+    /// Use the given token cache for request tokens
+    pub fn with_token_cache(mut self, tokens: Arc<TokenCache>) -> Self {
+        self.tokens = tokens;
+        self
+    }
 
-        debug!("Making GET request to {}", url);
+    /// Use the given retry policy instead of the default one
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
 
-        let response = self.client.get(&url)
-            .header(header::AUTHORIZATION, format!("Bearer {}", token))
-            .send()
-            .await
-            .context("Failed to send GET request")?;
+    /// Use a circuit breaker with the given settings instead of the default one
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = Arc::new(CircuitBreaker::new(config));
+        self
+    }
 
-        self.handle_response(response).await
+    /// Enable or disable automatic idempotency keys on POST and PUT requests
+    ///
+    /// Enabled by default. Without a key, POST requests are not retried.
+    pub fn with_idempotency_keys(mut self, enabled: bool) -> Self {
+        self.idempotency_keys = enabled;
+        self
     }
 
-    /// Make an authenticated PUT request
-    pub async fn put<T: DeserializeOwned, B: Serialize>(&self, path: &str, body:&B) -> Result<T> {
-        let url = format!("{}{}", self.base_url, path);
-        let token = self.auth_manager.create_auth_token(300)?; // 5 minute token
+    /// Follow configuration updates, e.g. from a
+    /// [`ConfigWatcher`](crate::config::ConfigWatcher)
+    ///
+    /// Each request uses the latest `api_url` and `request_timeout`.
+    pub fn with_config_updates(mut self, updates: watch::Receiver<Arc<ClientConfig>>) -> Self {
+        self.config = Some(updates);
+        self
+    }
 
-        debug!("Making a PUT request to {}", url);
+    /// Get the auth manager used to sign requests
+    pub fn auth_manager(&self) -> &Arc<AuthManager> {
+        &self.auth_manager
+    }
 
-        let response = self.client.put(&url)
-            .header(header::AUTHORIZATION, format!("Bearer {}", token))
-            .header(header::CONTENT_TYPE, "application/json")
-            .json(body)
-            .send()
-            .await
-            .context("Failed to senf PUT request")?;
+    /// Underlying reqwest client, sharing its connection pool and settings
+    pub(crate) fn reqwest_client(&self) -> &Client {
+        &self.client
+    }
 
-        self.handle_response(response).await
+    /// Get the cache supplying request tokens
+    pub fn token_cache(&self) -> &Arc<TokenCache> {
+        &self.tokens
+    }
+
+    /// Get the policy for retrying failed requests
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Get the circuit breaker guarding the API, shared by all clones
+    pub fn circuit_breaker(&self) -> &Arc<CircuitBreaker> {
+        &self.breaker
+    }
+
+    /// Make an authenticated GET request
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.send(Method::GET, path, None, None).await
+    }
+
+    /// Make an authenticated PUT request
+    ///
+    /// A new idempotency key is attached, shared by all retries of the request.
+    pub async fn put<T: DeserializeOwned, B: Serialize>(&self, path: &str, body:&B) -> Result<T> {
+        self.send(Method::PUT, path, Some(serde_json::to_vec(body)?), None).await
+    }
+
+    /// Make an authenticated PUT request with the given idempotency key
+    pub async fn put_with_idempotency_key<T: DeserializeOwned, B: Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+        idempotency_key: &str,
+    ) -> Result<T> {
+        self.send(Method::PUT, path, Some(serde_json::to_vec(body)?), Some(idempotency_key)).await
     }
 
     /// Make an authenticated DELETE request
     pub async fn delete<T:DeserializeOwned>(&self, path: &str) -> Result<T>{
-        let url = format!("{}{}", self.base_url, path);
-        let token = self.auth_manager.create_auth_token(300)?; // 5 minute token
-        
-        debug!("Making DELETE request to {}", url);
-        
-        let response = self.client.delete(&url)
-            .header(header::AUTHORIZATION, format!("Bearer {}", token))
-            .send()
-            .await
-            .context("Failed to send DELETE request")?;
-            
-        self.handle_response(response).await
+        self.send(Method::DELETE, path, None, None).await
     }
 
     /// Make an authenticated POST request
+    ///
+    /// A new idempotency key is attached, shared by all retries of the
+    /// request, so the server can drop duplicates. With idempotency keys
+    /// disabled POST requests are not retried, since the server may have
+    /// acted on a request whose response got lost.
     pub async fn post<T: DeserializeOwned, B: Serialize>(&self, path: &str, body: &B) -> Result<T> {
-        let url = format!("{}{}", self.base_url, path);
-        let token = self.auth_manager.create_auth_token(300)?; // 5 minute token
+        self.send(Method::POST, path, Some(serde_json::to_vec(body)?), None).await
+    }
+
+    /// Make an authenticated POST request with the given idempotency key
+    ///
+    /// Reusing the key for a later call, e.g. after a restart, lets the server
+    /// recognize it as a repeat of the earlier one.
+    pub async fn post_with_idempotency_key<T: DeserializeOwned, B: Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+        idempotency_key: &str,
+    ) -> Result<T> {
+        self.send(Method::POST, path, Some(serde_json::to_vec(body)?), Some(idempotency_key)).await
+    }
+
+    /// Send a request, retrying transient failures according to the retry policy
+    ///
+    /// Non-idempotent methods are only retried with an idempotency key.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+        idempotency_key: Option<&str>,
+    ) -> Result<T> {
+        let (url, timeout) = match &self.config {
+            Some(config) => {
+                let config = config.borrow();
+                (format!("{}{}", config.api_url, path), config.request_timeout.map(Duration::from_secs))
+            },
+            None => (format!("{}{}", self.base_url, path), None),
+        };
+
+        let idempotency_key = match idempotency_key {
+            Some(key) => Some(key.to_string()),
+            None if self.idempotency_keys && matches!(method, Method::POST | Method::PUT) => {
+                Some(generate_idempotency_key())
+            },
+            None => None,
+        };
+
+        let request = Request { method, url, body, timeout, idempotency_key };
+        let retryable = request.method.is_idempotent() || request.idempotency_key.is_some();
 
-        debug!("Making POST request to {}", url);
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            // Fetched outside the permit: a session login goes through the
+            // breaker and retry policy on its own
+            let token = self.tokens.token().await?;
 
-        let response = self.client.post(&url)
-            .header(header::AUTHORIZATION, format!("Bearer {}", token))
-            .header(header::CONTENT_TYPE, "application/json")
-            .json(body)
-            .send()
-            .await
-            .context("Failed to send POST request")?;
+            let permit = self.breaker.acquire()?;
+            let result = self.send_authorized(&request, token).await;
+            permit.record(&result);
+
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            attempt += 1;
+
+            let delay = retryable
+                .then(|| self.retry.next_delay(attempt, &error, started.elapsed()))
+                .flatten();
+            let Some(delay) = delay else {
+                return Err(error);
+            };
+
+            warn!(
+                "{} {} failed (attempt {}/{}), retrying in {:?}: {}",
+                request.method, request.url, attempt, self.retry.max_attempts, delay, error
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Send a request, retrying once with a fresh token if it is rejected with 401
+    async fn send_authorized<T: DeserializeOwned>(&self, request: &Request, token: String) -> Result<T> {
+        debug!("Making {} request to {}", request.method, request.url);
+
+        let mut response = self.send_once(request, &token).await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            debug!("Token rejected by {}, retrying with a new one", request.url);
+            self.tokens.invalidate().await;
+            let token = self.tokens.token().await?;
+            response = self.send_once(request, &token).await?;
+        }
 
         self.handle_response(response).await
     }
 
+    async fn send_once(&self, request: &Request, token: &str) -> Result<reqwest::Response> {
+        let mut builder = self.client.request(request.method.clone(), &request.url)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token));
+        if let Some(timeout) = request.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(key) = &request.idempotency_key {
+            builder = builder.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        if let Some(body) = &request.body {
+            builder = builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+        }
+
+        Ok(builder.send().await?)
+    }
+
     /// Handle API response
     async fn handle_response<T: DeserializeOwned>(&self, response: reqwest::Response) -> Result<T> {
-        let status = response.status();
-        let url = response.url().to_string();
-        if status.is_success() {
-            let body = response.json::<T>().await
-                .context("Failed to parse response JSON")?;
+        if response.status().is_success() {
+            let bytes = response.bytes().await?;
+            let body = serde_json::from_slice::<T>(&bytes)?;
             Ok(body)
         } else {
-            let error_text = response.text().await
-                .unwrap_or_else(|_| "Unable to read error response".to_string());
+            Err(error_from_response(response).await)
+        }
+    }
+}
 
-            error!("API error ({}): {} - {}", status.as_u16(), url, error_text);
+/// Turn an unsuccessful API response into [`SdkError::Http`]
+pub(crate) async fn error_from_response(response: reqwest::Response) -> SdkError {
+    let status = response.status();
+    let url = response.url().to_string();
+    let retry_after = response.headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, SystemTime::now()));
 
-            Err(anyhow::anyhow!("API error {}:{}", status.as_u16(), error_text))
-        }
+    let error_text = response.text().await
+        .unwrap_or_else(|_| "Unable to read error response".to_string());
+
+    error!("API error ({}): {} - {}", status.as_u16(), url, error_text);
+
+    let error = serde_json::from_str::<ApiError>(&error_text).ok().map(Box::new);
+
+    SdkError::Http {
+        status: status.as_u16(),
+        body: error_text,
+        error,
+        retry_after,
+    }
+}
+
+/// Parse a `Retry-After` value, either delay-seconds or an HTTP-date
+///
+/// A date in the past means the request can be retried right away.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+/// One logical request, resent as-is on every attempt
+struct Request {
+    method: Method,
+    url: String,
+    body: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    idempotency_key: Option<String>,
+}
+
+/// Generate a random idempotency key
+fn generate_idempotency_key() -> String {
+    let bytes: [u8; 16] = rand::rng().random();
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_delay_seconds() {
+        let now = SystemTime::now();
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-1", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn retry_after_http_date() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let value = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+        assert_eq!(parse_retry_after(value, date - Duration::from_secs(90)), Some(Duration::from_secs(90)));
+        assert_eq!(parse_retry_after(value, date + Duration::from_secs(90)), Some(Duration::ZERO));
+        // Obsolete RFC 850 and asctime forms are accepted too
+        assert_eq!(parse_retry_after("Sunday, 06-Nov-94 08:49:37 GMT", date), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Sun Nov  6 08:49:37 1994", date), Some(Duration::ZERO));
+    }
+}
@@ -1,8 +1,23 @@
 //! Communication modules for the IoT SDK
 
+pub mod breaker;
+pub mod commands;
+pub mod delivery;
 pub mod http;
 pub mod websocket;
+pub mod offline;
+pub mod retry;
+pub mod subscription;
+pub mod tls;
+pub mod util;
 
 // Re-export important types
+pub use breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitState};
+pub use commands::{CommandRegistry, CommandRequest, CommandResponse, CommandStatus};
+pub use delivery::{DeliveryGuarantee, DeliveryPolicy};
 pub use http::HttpClient;
-pub use websocket::{WebSocketConnection, WebSocketMessage, WebSocketMessageType};
\ No newline at end of file
+pub use offline::{EvictionPolicy, OfflineQueue, OfflineQueueConfig};
+pub use retry::RetryPolicy;
+pub use subscription::{LagPolicy, MessageStream, SubscriptionOptions};
+pub use tls::{TlsConfig, TlsVersion};
+pub use websocket::{HeartbeatConfig, ReconnectPolicy, WebSocketAuthMode, WebSocketConnection, WebSocketMessage, WebSocketMessageType};
\ No newline at end of file
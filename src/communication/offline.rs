@@ -0,0 +1,371 @@
+//! Durable on-disk queue for messages sent while offline
+//!
+//! Messages are appended as JSON lines to numbered segment files. Once a
+//! segment is full a new one is started, and segments are drained and
+//! deleted oldest first when the connection comes back.
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+use crate::error::{Result, SdkError};
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+/// What to do when the queue reaches its size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Delete the oldest segments to make room
+    #[default]
+    DropOldest,
+
+    /// Refuse new messages until the queue is drained
+    RejectNew,
+}
+
+/// Configuration for the offline queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfflineQueueConfig {
+    /// Directory holding the segment files
+    pub directory: String,
+
+    /// Size at which a new segment file is started, in bytes
+    #[serde(default = "default_max_segment_bytes")]
+    pub max_segment_bytes: u64,
+
+    /// Total size of all segments, in bytes
+    #[serde(default = "default_max_total_bytes")]
+    pub max_total_bytes: u64,
+
+    /// Messages older than this are discarded instead of sent, in seconds
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+
+    /// Behavior once `max_total_bytes` is reached
+    #[serde(default)]
+    pub eviction: EvictionPolicy,
+}
+
+fn default_max_segment_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_max_total_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+impl OfflineQueueConfig {
+    /// Create a configuration with default limits
+    pub fn new<S: Into<String>>(directory: S) -> Self {
+        Self {
+            directory: directory.into(),
+            max_segment_bytes: default_max_segment_bytes(),
+            max_total_bytes: default_max_total_bytes(),
+            max_age_secs: None,
+            eviction: EvictionPolicy::default(),
+        }
+    }
+}
+
+/// A single queued message as stored on disk
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    queued_at: u64,
+    message: String,
+}
+
+/// The `id` of a queued WebSocket message, if it has one
+#[derive(Deserialize)]
+struct MessageId {
+    id: Option<String>,
+}
+
+/// A sealed segment handed out for draining
+pub(crate) struct Segment {
+    path: PathBuf,
+    pub(crate) messages: Vec<String>,
+}
+
+struct State {
+    /// Sequence numbers of segments on disk, oldest first
+    segments: Vec<u64>,
+    next_seq: u64,
+    total_bytes: u64,
+    active_bytes: u64,
+
+    /// The newest segment is being drained and must not be appended to
+    sealed: bool,
+
+    /// IDs of messages queued by this process, with their segment, so a
+    /// message isn't stored twice when both the session and an
+    /// at-least-once resend fall back to the queue
+    queued_ids: HashMap<String, u64>,
+}
+
+/// Append-only outbound queue persisted to disk
+pub struct OfflineQueue {
+    config: OfflineQueueConfig,
+    dir: PathBuf,
+    state: Mutex<State>,
+}
+
+impl OfflineQueue {
+    /// Open the queue, creating its directory and picking up existing segments
+    pub fn open(config: OfflineQueueConfig) -> Result<Self> {
+        let dir = PathBuf::from(&config.directory);
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        let mut total_bytes = 0;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            if let Some(seq) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()) {
+                total_bytes += fs::metadata(&path)?.len();
+                segments.push(seq);
+            }
+        }
+        segments.sort_unstable();
+
+        let active_bytes = match segments.last() {
+            Some(seq) => fs::metadata(segment_path(&dir, *seq))?.len(),
+            None => 0,
+        };
+
+        debug!("Opened offline queue with {} segments ({} bytes)", segments.len(), total_bytes);
+
+        Ok(Self {
+            config,
+            dir,
+            state: Mutex::new(State {
+                next_seq: segments.last().map_or(1, |seq| seq + 1),
+                segments,
+                total_bytes,
+                active_bytes,
+                sealed: false,
+                queued_ids: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Append a serialized message to the queue
+    ///
+    /// A message whose `id` is already queued is skipped. Blocks on an
+    /// fsync; from async code use [`OfflineQueue::push_async`].
+    pub fn push(&self, message: &str) -> Result<()> {
+        let id = serde_json::from_str::<MessageId>(message).ok().and_then(|m| m.id);
+        let record = Record {
+            queued_at: now_secs(),
+            message: message.to_string(),
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        let len = line.len() as u64;
+
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(id) = &id {
+            if state.queued_ids.contains_key(id) {
+                debug!("Message {} is already in the offline queue", id);
+                return Ok(());
+            }
+        }
+
+        while state.total_bytes + len > self.config.max_total_bytes {
+            match self.config.eviction {
+                EvictionPolicy::RejectNew => return Err(SdkError::QueueFull),
+                EvictionPolicy::DropOldest => {
+                    if state.segments.is_empty() {
+                        return Err(SdkError::QueueFull);
+                    }
+                    let seq = state.segments.remove(0);
+                    let path = segment_path(&self.dir, seq);
+                    state.total_bytes = state.total_bytes.saturating_sub(remove_segment_file(&path)?);
+                    state.queued_ids.retain(|_, queued_seq| *queued_seq != seq);
+                    if state.segments.is_empty() {
+                        state.active_bytes = 0;
+                    }
+                    warn!("Offline queue full, dropped segment {}", seq);
+                }
+            }
+        }
+
+        if state.segments.is_empty() || state.sealed
+            || state.active_bytes + len > self.config.max_segment_bytes {
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.segments.push(seq);
+            state.active_bytes = 0;
+            state.sealed = false;
+        }
+
+        let seq = *state.segments.last().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&self.dir, seq))?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        state.active_bytes += len;
+        state.total_bytes += len;
+        if let Some(id) = id {
+            state.queued_ids.insert(id, seq);
+        }
+
+        Ok(())
+    }
+
+    /// Append a serialized message on a blocking thread, keeping the disk
+    /// writes off the async runtime
+    pub async fn push_async(self: &Arc<Self>, message: String) -> Result<()> {
+        self.blocking(move |queue| queue.push(&message)).await
+    }
+
+    /// Check if the queue holds no messages
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().segments.is_empty()
+    }
+
+    /// Total size of the queued segments, in bytes
+    pub fn size_bytes(&self) -> u64 {
+        self.state.lock().unwrap().total_bytes
+    }
+
+    /// Take the oldest segment for draining
+    ///
+    /// The segment stays on disk until it is removed after a successful
+    /// drain, so a crash mid-drain resends it. Messages older than `max_age_secs` are
+    /// skipped.
+    pub(crate) fn oldest(&self) -> Result<Option<Segment>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(&seq) = state.segments.first() else {
+            return Ok(None);
+        };
+
+        // Seal the segment so new messages don't land in it while draining
+        if state.segments.len() == 1 {
+            state.sealed = true;
+        }
+
+        let path = segment_path(&self.dir, seq);
+        let cutoff = self.config.max_age_secs.map(|age| now_secs().saturating_sub(age));
+        let mut messages = Vec::new();
+        let mut expired = 0;
+
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) if cutoff.is_some_and(|cutoff| record.queued_at < cutoff) => expired += 1,
+                Ok(record) => messages.push(record.message),
+                // A torn write at the end of a segment after a crash
+                Err(e) => warn!("Skipping corrupt record in {}: {}", path.display(), e),
+            }
+        }
+
+        if expired > 0 {
+            warn!("Discarded {} expired messages from offline queue", expired);
+        }
+
+        Ok(Some(Segment { path, messages }))
+    }
+
+    /// [`OfflineQueue::oldest`] on a blocking thread
+    pub(crate) async fn oldest_async(self: &Arc<Self>) -> Result<Option<Segment>> {
+        self.blocking(|queue| queue.oldest()).await
+    }
+
+    /// [`OfflineQueue::remove`] on a blocking thread
+    pub(crate) async fn remove_async(self: &Arc<Self>, segment: Segment) -> Result<()> {
+        self.blocking(move |queue| queue.remove(segment)).await
+    }
+
+    async fn blocking<T, F>(self: &Arc<Self>, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&OfflineQueue) -> Result<T> + Send + 'static,
+    {
+        let queue = self.clone();
+        tokio::task::spawn_blocking(move || f(&queue))
+            .await
+            .map_err(|e| SdkError::Io(std::io::Error::other(e)))?
+    }
+
+    /// Delete a drained segment
+    pub(crate) fn remove(&self, segment: Segment) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        // The segment may already have been evicted while it was being drained
+        let Some(index) = state.segments.iter().position(|seq| segment_path(&self.dir, *seq) == segment.path) else {
+            return Ok(());
+        };
+
+        let seq = state.segments.remove(index);
+        state.queued_ids.retain(|_, queued_seq| *queued_seq != seq);
+        state.total_bytes = state.total_bytes.saturating_sub(remove_segment_file(&segment.path)?);
+        if state.segments.is_empty() {
+            state.active_bytes = 0;
+        }
+
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION))
+}
+
+/// Delete a segment file, returning its size
+fn remove_segment_file(path: &Path) -> Result<u64> {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    match fs::remove_file(path) {
+        Ok(()) => Ok(size),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_queue(name: &str) -> (OfflineQueue, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("iot-sdk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        (OfflineQueue::open(OfflineQueueConfig::new(dir.to_string_lossy())).unwrap(), dir)
+    }
+
+    #[test]
+    fn push_skips_messages_already_queued() {
+        let (queue, dir) = open_queue("offline-dedupe");
+
+        queue.push(r#"{"id":"a","payload":1}"#).unwrap();
+        queue.push(r#"{"id":"a","payload":1}"#).unwrap();
+        queue.push(r#"{"id":"b","payload":2}"#).unwrap();
+        queue.push("not json").unwrap();
+        queue.push("not json").unwrap();
+
+        let segment = queue.oldest().unwrap().unwrap();
+        assert_eq!(segment.messages.len(), 4);
+        queue.remove(segment).unwrap();
+
+        // Once drained, a resend is queued again
+        queue.push(r#"{"id":"a","payload":1}"#).unwrap();
+        assert_eq!(queue.oldest().unwrap().unwrap().messages.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
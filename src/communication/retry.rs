@@ -0,0 +1,67 @@
+//! Retry settings for HTTP requests
+
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use crate::communication::util::backoff_with_jitter;
+use crate::error::SdkError;
+
+/// When and how often failed HTTP requests are retried
+///
+/// Only transient failures are retried: connection errors, timeouts, `429`
+/// and `5xx` responses. A `Retry-After` header from the server replaces the
+/// computed backoff. POST requests are retried only when they carry an
+/// idempotency key, since the server may have processed the first attempt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first; `1` disables retries
+    pub max_attempts: usize,
+
+    /// Delay before the first retry, in milliseconds
+    pub base_delay_ms: u64,
+
+    /// Upper bound for the computed delay between attempts, in milliseconds
+    pub max_delay_ms: u64,
+
+    /// No retry is started that would end later than this after the first
+    /// attempt, in milliseconds
+    pub max_elapsed_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 200,
+            max_delay_ms: 5_000,
+            max_elapsed_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the next attempt, or `None` to give up
+    ///
+    /// `attempt` is the number of attempts made so far and `elapsed` the
+    /// time since the first one started.
+    pub fn next_delay(&self, attempt: usize, error: &SdkError, elapsed: Duration) -> Option<Duration> {
+        if attempt >= self.max_attempts || !error.is_retryable() {
+            return None;
+        }
+
+        let delay = error.retry_after()
+            .unwrap_or_else(|| backoff_with_jitter(attempt - 1, self.base_delay_ms, self.max_delay_ms));
+
+        elapsed.checked_add(delay)
+            .is_some_and(|end| end <= Duration::from_millis(self.max_elapsed_ms))
+            .then_some(delay)
+    }
+}
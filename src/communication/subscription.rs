@@ -0,0 +1,85 @@
+//! Streams of messages received over the WebSocket
+
+use futures_util::stream::{self, Stream};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use crate::communication::websocket::{WebSocketMessage, WebSocketMessageType};
+
+/// What a subscriber does when it falls behind and messages are overwritten
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LagPolicy {
+    /// Skip the overwritten messages and keep streaming
+    #[default]
+    Skip,
+
+    /// End the stream so the consumer can notice and resubscribe
+    Terminate,
+}
+
+/// Options for subscribing to incoming messages
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionOptions {
+    /// Only yield messages of these types (all types if empty)
+    pub message_types: Vec<WebSocketMessageType>,
+
+    /// Behavior when the subscriber lags behind
+    pub lag_policy: LagPolicy,
+}
+
+impl SubscriptionOptions {
+    /// Only yield messages of the given types
+    pub fn with_types(mut self, types: &[WebSocketMessageType]) -> Self {
+        self.message_types = types.to_vec();
+        self
+    }
+
+    /// Set the lag policy
+    pub fn with_lag_policy(mut self, policy: LagPolicy) -> Self {
+        self.lag_policy = policy;
+        self
+    }
+}
+
+/// Stream of messages received from the server
+///
+/// Each subscriber gets its own copy of every message received after it
+/// subscribed. The stream ends when the connection is dropped.
+pub struct MessageStream {
+    inner: Pin<Box<dyn Stream<Item = WebSocketMessage> + Send>>,
+}
+
+impl MessageStream {
+    pub(crate) fn new(rx: broadcast::Receiver<WebSocketMessage>, options: SubscriptionOptions) -> Self {
+        let inner = stream::unfold((rx, options), |(mut rx, options)| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(message) => {
+                        if options.message_types.is_empty()
+                            || options.message_types.contains(&message.message_type) {
+                            return Some((message, (rx, options)));
+                        }
+                    },
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Message subscriber lagged behind, {} messages dropped", skipped);
+                        if options.lag_policy == LagPolicy::Terminate {
+                            return None;
+                        }
+                    },
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Self { inner: Box::pin(inner) }
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = WebSocketMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
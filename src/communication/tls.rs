@@ -0,0 +1,340 @@
+//! TLS settings shared by the HTTP and WebSocket transports
+
+use base64::{engine::general_purpose, Engine as _};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use crate::error::{Result, SdkError};
+
+/// Lowest TLS version the client will negotiate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TlsVersion {
+    /// TLS 1.2 or newer
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+
+    /// TLS 1.3 only
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// TLS settings for connections to the service
+///
+/// Without a CA bundle, servers are checked against the Mozilla root store.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with the CA certificates to trust instead of the public roots
+    pub ca_bundle_path: Option<String>,
+
+    /// PEM file with the client certificate chain for mutual TLS
+    pub client_cert_path: Option<String>,
+
+    /// PEM file with the private key of the client certificate
+    pub client_key_path: Option<String>,
+
+    /// Base64 SHA-256 hashes of trusted SubjectPublicKeyInfos; when set, some
+    /// certificate in the server's chain must match one of them
+    #[serde(default)]
+    pub pinned_spki_sha256: Vec<String>,
+
+    /// Lowest TLS version to accept
+    #[serde(default)]
+    pub min_version: TlsVersion,
+}
+
+impl TlsConfig {
+    /// Create a configuration with default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust only the CA certificates in a PEM file
+    pub fn with_ca_bundle<S: Into<String>>(mut self, path: S) -> Self {
+        self.ca_bundle_path = Some(path.into());
+        self
+    }
+
+    /// Present a client certificate for mutual TLS
+    pub fn with_client_cert<S: Into<String>>(mut self, cert_path: S, key_path: S) -> Self {
+        self.client_cert_path = Some(cert_path.into());
+        self.client_key_path = Some(key_path.into());
+        self
+    }
+
+    /// Pin a SubjectPublicKeyInfo by its base64 SHA-256 hash
+    pub fn with_pinned_spki<S: Into<String>>(mut self, hash: S) -> Self {
+        self.pinned_spki_sha256.push(hash.into());
+        self
+    }
+
+    /// Set the lowest TLS version to accept
+    pub fn with_min_version(mut self, version: TlsVersion) -> Self {
+        self.min_version = version;
+        self
+    }
+
+    /// Build the rustls configuration used by both transports
+    pub fn build(&self) -> Result<Arc<ClientConfig>> {
+        let provider = Arc::new(ring::default_provider());
+
+        let mut roots = RootCertStore::empty();
+        match &self.ca_bundle_path {
+            Some(path) => {
+                for cert in read_certs(path)? {
+                    roots.add(cert)
+                        .map_err(|e| SdkError::Config(format!("Invalid CA certificate in {}: {}", path, e)))?;
+                }
+            },
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(|e| SdkError::Config(format!("Failed to build certificate verifier: {}", e)))?;
+
+        let versions: &[&rustls::SupportedProtocolVersion] = match self.min_version {
+            TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+            TlsVersion::Tls13 => &[&rustls::version::TLS13],
+        };
+
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(versions)
+            .map_err(|e| SdkError::Config(format!("Unsupported TLS versions: {}", e)))?;
+
+        let builder = if self.pinned_spki_sha256.is_empty() {
+            builder.with_webpki_verifier(verifier)
+        } else {
+            let pins = self.pinned_spki_sha256.iter()
+                .map(|pin| decode_pin(pin))
+                .collect::<Result<Vec<_>>>()?;
+            builder.dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedVerifier { inner: verifier, pins }))
+        };
+
+        let config = match (&self.client_cert_path, &self.client_key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                let key = PrivateKeyDer::from_pem_file(key_path)
+                    .map_err(|e| SdkError::Config(format!("Failed to read client key {}: {}", key_path, e)))?;
+                builder.with_client_auth_cert(read_certs(cert_path)?, key)
+                    .map_err(|e| SdkError::Config(format!("Invalid client certificate: {}", e)))?
+            },
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(SdkError::Config(
+                "client_cert_path and client_key_path must be set together".to_string(),
+            )),
+        };
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// Base64 SHA-256 hash of a certificate's SubjectPublicKeyInfo, as used for pinning
+pub fn spki_sha256(cert_der: &[u8]) -> Option<String> {
+    spki(cert_der).map(|spki| general_purpose::STANDARD.encode(Sha256::digest(spki)))
+}
+
+/// Checks the chain with webpki, then requires a pinned public key in it
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| spki(cert))
+            .any(|spki| self.pins.contains(&Sha256::digest(spki).into()));
+
+        if pinned {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General("No certificate in the chain matches a pinned key".to_string()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Read all certificates from a PEM file
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| SdkError::Config(format!("Failed to read certificates from {}: {}", path, e)))?;
+
+    if certs.is_empty() {
+        return Err(SdkError::Config(format!("No certificates found in {}", path)));
+    }
+    Ok(certs)
+}
+
+pub(crate) fn decode_pin(pin: &str) -> Result<[u8; 32]> {
+    general_purpose::STANDARD.decode(pin)
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .ok_or_else(|| SdkError::Config(format!("Invalid SPKI pin {}: expected a base64 SHA-256 hash", pin)))
+}
+
+/// The DER SubjectPublicKeyInfo of an X.509 certificate
+fn spki(cert_der: &[u8]) -> Option<&[u8]> {
+    // Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { ... } ... }
+    let (_, certificate, _) = der_element(cert_der)?;
+    let (_, mut tbs, _) = der_element(certificate)?;
+
+    // Skip the optional [0] version, serial, signature algorithm, issuer,
+    // validity and subject; the next element is the SubjectPublicKeyInfo
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs)?.2;
+    }
+    for _ in 0..5 {
+        tbs = der_element(tbs)?.2;
+    }
+
+    let (tag, _, rest) = der_element(tbs)?;
+    (tag == 0x30).then(|| &tbs[..tbs.len() - rest.len()])
+}
+
+/// Split one DER element into its tag, contents and the bytes after it
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first_len = *data.get(1)?;
+
+    let (len, header) = if first_len < 0x80 {
+        (first_len as usize, 2)
+    } else {
+        let count = (first_len & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let len = data.get(2..2 + count)?
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, 2 + count)
+    };
+
+    let contents = data.get(header..header.checked_add(len)?)?;
+    Some((tag, contents, &data[header + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed P-256 v3 certificate with extensions
+    const V3_CERT: &str = "MIIBejCCAR+gAwIBAgIUK2ZEQ15MqdytLEckulEb/Xg2ICwwCgYIKoZIzj0EAwIwEjEQMA4GA1UEAwwHdjMudGVzdDAeFw0yNjEwMTYyMDA4NTZaFw0zNjEwMTMyMDA4NTZaMBIxEDAOBgNVBAMMB3YzLnRlc3QwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAARzrCgkyZe4P7zI9QfADjA/VxKV4TJPTQdZrEHemdKGEYaorcdJEbsB6+Pl6iY73AB+8gzsb87JfTCdQSEb/dMIo1MwUTAdBgNVHQ4EFgQUN4LYm+2SXKTI9LcIznv42vy5lj8wHwYDVR0jBBgwFoAUN4LYm+2SXKTI9LcIznv42vy5lj8wDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNJADBGAiEA9w+GydoJObUnebFx0IeQq3R2d5UJy07EpxxcDu2AmjUCIQCDCpbV0CsAuyWEBep7HaH2+cZ34+vk5q+58hpH1upGXw==";
+
+    /// v1 certificate for the same key, without the `[0]` version tag
+    const V1_CERT: &str = "MIIBDDCBsgIBATAKBggqhkjOPQQDAjASMRAwDgYDVQQDDAd2MS50ZXN0MB4XDTI2MDEwMTAwMDAwMFoXDTM2MDEwMTAwMDAwMFowEjEQMA4GA1UEAwwHdjEudGVzdDBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABHOsKCTJl7g/vMj1B8AOMD9XEpXhMk9NB1msQd6Z0oYRhqitx0kRuwHr4+XqJjvcAH7yDOxvzsl9MJ1BIRv90wgwCgYIKoZIzj0EAwIDSQAwRgIhAL2IJwzZ8VoXUK8d2DY2BUP1hzWn8l3skecLdSiyGIeLAiEAjsonMxqVEmuExq333wQP8ZSFrqprEPmBtjJ0qjUP2Qs=";
+
+    /// Pin of both certificates' key, computed with
+    /// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`
+    const PIN: &str = "Q0Sh/3Vn3PrXfM4PwOteWL+fyR/PxCRg5aLVPUSUb/A=";
+
+    fn der(cert: &str) -> Vec<u8> {
+        general_purpose::STANDARD.decode(cert).unwrap()
+    }
+
+    #[test]
+    fn spki_of_v3_certificate() {
+        let cert = der(V3_CERT);
+        let spki = spki(&cert).unwrap();
+        // SEQUENCE of 0x59 bytes: id-ecPublicKey on P-256 and a 65-byte point
+        assert_eq!(&spki[..2], &[0x30, 0x59]);
+        assert_eq!(spki.len(), 0x5b);
+        assert_eq!(spki_sha256(&cert).as_deref(), Some(PIN));
+    }
+
+    #[test]
+    fn spki_of_certificate_without_version() {
+        let cert = der(V1_CERT);
+        assert_ne!(cert[4], 0xa0);
+        assert_eq!(spki_sha256(&cert).as_deref(), Some(PIN));
+    }
+
+    #[test]
+    fn pin_round_trips_through_decode() {
+        let cert = der(V3_CERT);
+        let expected: [u8; 32] = Sha256::digest(spki(&cert).unwrap()).into();
+        assert_eq!(decode_pin(PIN).unwrap(), expected);
+        assert!(decode_pin("not a pin").is_err());
+        assert!(decode_pin(&general_purpose::STANDARD.encode([0u8; 16])).is_err());
+    }
+
+    #[test]
+    fn der_element_short_and_long_form_lengths() {
+        assert_eq!(der_element(&[0x04, 0x02, 1, 2, 9]), Some((0x04, &[1, 2][..], &[9][..])));
+
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend([7; 0x80]);
+        long.push(9);
+        let (tag, contents, rest) = der_element(&long).unwrap();
+        assert_eq!((tag, contents.len(), rest), (0x04, 0x80, &[9][..]));
+
+        let mut two_bytes = vec![0x30, 0x82, 0x01, 0x00];
+        two_bytes.extend([0; 0x100]);
+        assert_eq!(der_element(&two_bytes).unwrap().1.len(), 0x100);
+    }
+
+    #[test]
+    fn der_element_rejects_malformed_input() {
+        assert_eq!(der_element(&[]), None);
+        assert_eq!(der_element(&[0x30]), None);
+        // Contents shorter than the declared length
+        assert_eq!(der_element(&[0x30, 0x03, 1, 2]), None);
+        // Indefinite length, and more length bytes than supported
+        assert_eq!(der_element(&[0x30, 0x80, 0, 0]), None);
+        assert_eq!(der_element(&[0x30, 0x85, 0, 0, 0, 0, 1, 0]), None);
+        // Length bytes missing, and a length that would overflow
+        assert_eq!(der_element(&[0x30, 0x82, 0x01]), None);
+        assert_eq!(der_element(&[0x30, 0x84, 0xff, 0xff, 0xff, 0xff]), None);
+    }
+
+    #[test]
+    fn spki_rejects_truncated_or_garbage_certificates() {
+        let cert = der(V3_CERT);
+        for len in [0, 1, 4, 40, cert.len() / 2, cert.len() - 1] {
+            assert_eq!(spki(&cert[..len]), None, "truncated to {} bytes", len);
+        }
+        assert_eq!(spki(b"not a certificate"), None);
+        assert_eq!(spki_sha256(&[0x30, 0x03, 0x30, 0x01, 0x00]), None);
+        // Well-formed outer SEQUENCEs around a tbsCertificate holding only a serial
+        assert_eq!(spki(&[0x30, 0x05, 0x30, 0x03, 0x02, 0x01, 0x01]), None);
+    }
+}
@@ -1,5 +1,5 @@
 //! Utility functions for communication
-use anyhow::Result;
+use crate::error::Result;
 use tokio::time::{sleep, Duration};
 use tracing::warn;
 
@@ -42,13 +42,13 @@ where
 
 /// Calculate exponential backff delay with jitter
 pub fn backoff_with_jitter(attempt: usize, base_delay_ms: u64, max_delay_ms: u64) -> Duration {
-    use rand::{thread_rng, Rng};
+    use rand::Rng;
     
-    let exp_backoff = base_delay_ms * (1u64 << attempt.min(31));
+    let exp_backoff = base_delay_ms.saturating_mul(1u64 << attempt.min(31));
     let capped_backoff = exp_backoff.min(max_delay_ms);
     
     // Add jitter - random value between 0-20% of the delay
-    let jitter_factor = thread_rng().gen_range(0.0..0.2);
+    let jitter_factor = rand::rng().random_range(0.0..0.2);
     let jitter = (capped_backoff as f64 * jitter_factor) as u64;
     
     Duration::from_millis(capped_backoff + jitter)
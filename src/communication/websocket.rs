@@ -1,25 +1,162 @@
 //! WebSocket communication for real-time updates
 
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
-use futures_util::{SinkExt, StreamExt};
-use anyhow::{Result, Context, anyhow};
+use tokio_tungstenite::{connect_async_tls_with_config, Connector, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{header, HeaderValue};
+use tokio_tungstenite::tungstenite::{self, protocol::{CloseFrame, Message}};
+use futures_util::{SinkExt, StreamExt, stream::SplitSink};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
-use tracing::{info, error, debug};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{info, warn, error, debug};
 use serde::{Serialize, Deserialize};
 use std::time::Duration;
-use tokio::time::sleep;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
 use rand::{rngs::ThreadRng, Rng};
 use base64::{engine::general_purpose, Engine as _};
+use url::Url;
+use crate::auth::TokenCache;
+use crate::communication::commands::{CommandRegistry, CommandRequest, CommandResponse};
+use crate::communication::delivery::{DeliveryGuarantee, PendingAcks};
+use crate::communication::offline::OfflineQueue;
+use crate::communication::subscription::{MessageStream, SubscriptionOptions};
+use crate::communication::util::backoff_with_jitter;
+use crate::config::ClientConfig;
+use crate::error::{Result, SdkError};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Default number of incoming messages buffered for slow subscribers
+const DEFAULT_MESSAGE_CAPACITY: usize = 256;
+
+/// How long `close()` waits for the close handshake to be sent
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Subprotocol the server echoes back when subprotocol auth is used
+const SUBPROTOCOL: &str = "iot-dash.v1";
+
+/// Prefix of the subprotocol entry carrying the token
+const SUBPROTOCOL_TOKEN_PREFIX: &str = "iot-dash.bearer.";
 
 /// A WebSocket connection to the IoT service
 pub struct WebSocketConnection {
     send_tx: Option<mpsc::Sender<String>>,
     connected: Arc<Mutex<bool>>,
+    worker: Option<JoinHandle<()>>,
+    supervised: bool,
+    commands: CommandRegistry,
+    incoming: broadcast::Sender<WebSocketMessage>,
+    delivery: DeliveryGuarantee,
+    pending: PendingAcks,
+    offline: Option<Arc<OfflineQueue>>,
+    heartbeat: Option<HeartbeatConfig>,
+    auth_mode: WebSocketAuthMode,
+    tls: Option<Arc<rustls::ClientConfig>>,
+    config: Option<watch::Receiver<Arc<ClientConfig>>>,
+}
+
+/// Reconnection settings for a supervised connection
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay before the first reconnection attempt, in milliseconds
+    pub base_delay_ms: u64,
+
+    /// Upper bound for the delay between attempts, in milliseconds
+    pub max_delay_ms: u64,
+
+    /// Consecutive failed attempts before giving up (`None` retries forever)
+    pub max_attempts: Option<usize>,
+
+    /// How long a session must stay up before the backoff resets, in
+    /// milliseconds; shorter sessions count as failed attempts
+    pub min_stable_ms: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay_ms: 1_000,
+            max_delay_ms: 60_000,
+            max_attempts: None,
+            min_stable_ms: 10_000,
+        }
+    }
+}
+
+/// How the auth token is sent on the WebSocket handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebSocketAuthMode {
+    /// `Authorization: Bearer <token>` header
+    #[default]
+    Header,
+
+    /// `Sec-WebSocket-Protocol: iot-dash.v1, iot-dash.bearer.<base64url token>`,
+    /// for servers or proxies that can't read custom handshake headers
+    Subprotocol,
+
+    /// `?token=<token>` query parameter (legacy, may leak into logs)
+    QueryParam,
+}
+
+/// Keepalive settings for detecting dead connections
+#[derive(Debug, Clone)]
+pub struct HeartbeatConfig {
+    /// How often to send a ping
+    pub ping_interval: Duration,
+
+    /// How long to wait for the matching pong before declaring the peer dead
+    pub pong_timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// State shared by every session of a connection
+#[derive(Clone)]
+struct SessionContext {
+    url: String,
+    device_id: String,
+    commands: CommandRegistry,
+    incoming: broadcast::Sender<WebSocketMessage>,
+    pending: PendingAcks,
+    offline: Option<Arc<OfflineQueue>>,
+    heartbeat: Option<HeartbeatConfig>,
+    auth_mode: WebSocketAuthMode,
+    tls: Option<Arc<rustls::ClientConfig>>,
+    config: Option<watch::Receiver<Arc<ClientConfig>>>,
+}
+
+/// Where an outgoing message ended up
+enum Queued {
+    /// Handed to the socket sender
+    Socket,
+
+    /// Stored in the offline queue until the connection is back
+    Offline,
+}
+
+/// Why a session loop ended
+enum SessionEnd {
+    /// The socket failed, the server closed it, or the peer stopped answering pings
+    SocketClosed,
+
+    /// Every sender handle was dropped
+    ChannelClosed,
+
+    /// The configured WebSocket URL changed
+    Reconfigured,
 }
 
 /// Message type for WebSocket communication
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum WebSocketMessageType {
     #[serde(rename = "data")]
     Data,
@@ -35,7 +172,7 @@ pub enum WebSocketMessageType {
 }
 
 /// Message structure for WebSocket communication
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebSocketMessage {
     #[serde(rename = "type")]
     pub message_type: WebSocketMessageType,
@@ -50,99 +187,272 @@ pub struct WebSocketMessage {
     pub timestamp: u64,
 }
 
+impl Default for WebSocketConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WebSocketConnection {
     /// Create a new WebSocket connection (not connected yet)
     pub fn new() -> Self {
+        Self::with_message_capacity(DEFAULT_MESSAGE_CAPACITY)
+    }
+
+    /// Create a new connection buffering up to `capacity` incoming messages per subscriber
+    ///
+    /// Subscribers that fall further behind than this lose messages, as
+    /// decided by their [`LagPolicy`](crate::communication::LagPolicy).
+    pub fn with_message_capacity(capacity: usize) -> Self {
+        let (incoming, _) = broadcast::channel(capacity.max(1));
+
         Self {
             send_tx: None,
             connected: Arc::new(Mutex::new(false)),
+            worker: None,
+            supervised: false,
+            commands: CommandRegistry::new(),
+            incoming,
+            delivery: DeliveryGuarantee::default(),
+            pending: PendingAcks::default(),
+            offline: None,
+            heartbeat: Some(HeartbeatConfig::default()),
+            auth_mode: WebSocketAuthMode::default(),
+            tls: None,
+            config: None,
         }
     }
+
+    /// Set how the auth token is sent on the handshake
+    pub fn set_auth_mode(&mut self, mode: WebSocketAuthMode) {
+        self.auth_mode = mode;
+    }
+
+    /// Use the given TLS settings for `wss://` connections instead of the defaults
+    pub fn set_tls(&mut self, tls: Arc<rustls::ClientConfig>) {
+        self.tls = Some(tls);
+    }
+
+    /// Follow configuration updates, e.g. from a
+    /// [`ConfigWatcher`](crate::config::ConfigWatcher)
+    ///
+    /// A supervised connection reconnects when `websocket_url` changes.
+    pub fn set_config_updates(&mut self, updates: watch::Receiver<Arc<ClientConfig>>) {
+        self.config = Some(updates);
+    }
+
+    /// Set the keepalive settings, or disable pings with `None`
+    ///
+    /// Takes effect on the next (re-)connection.
+    pub fn set_heartbeat(&mut self, heartbeat: Option<HeartbeatConfig>) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Buffer messages in an on-disk queue while disconnected
+    ///
+    /// Queued messages are sent in order, ahead of anything new, as soon as
+    /// the connection is (re-)established.
+    pub fn set_offline_queue(&mut self, queue: OfflineQueue) {
+        self.offline = Some(Arc::new(queue));
+    }
+
+    /// Set how data and status messages are delivered
+    pub fn set_delivery(&mut self, delivery: DeliveryGuarantee) {
+        self.delivery = delivery;
+    }
+
+    /// Number of sent messages still waiting for an acknowledgement
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Get the registry of handlers for incoming commands
+    ///
+    /// Handlers can be registered at any time, including while connected.
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    /// Subscribe to every message received from the server
+    pub fn subscribe(&self) -> MessageStream {
+        self.subscribe_with(SubscriptionOptions::default())
+    }
+
+    /// Subscribe to received messages with type filtering and a lag policy
+    pub fn subscribe_with(&self, options: SubscriptionOptions) -> MessageStream {
+        MessageStream::new(self.incoming.subscribe(), options)
+    }
     
     /// Connect to the WebSocket server
     pub async fn connect(&mut self, url: &str, auth_token: &str, device_id: &str) -> Result<()> {
-        let full_url = format!("{}?token={}&device_id={}", url, auth_token, device_id);
-        let (ws_stream, _) = connect_async(&full_url)
-            .await
-            .context("Failed to connect to WebSocket server")?;
-        
+        self.stop_worker();
+
+        let ws_stream = open_stream(url, auth_token, device_id, self.auth_mode, self.tls.clone()).await?;
         info!("WebSocket connected to {}", url);
         
-        let (mut write, mut read) = ws_stream.split();
-        
         // Channel for sending messages to the WebSocket
         let (tx, mut rx) = mpsc::channel::<String>(100);
-        self.send_tx = Some(tx.clone());
+        self.send_tx = Some(tx);
         
         // Set connected state
         let connected = self.connected.clone();
         *connected.lock().unwrap() = true;
         
-        // Task for sending messages
-        let connected_clone = connected.clone();
-        tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                if let Err(e) = write.send(Message::Text(message.into())).await {
-                    error!("Error sending WebSocket message: {}", e);
-                    *connected_clone.lock().unwrap() = false;
-                    break;
-                }
-            }
-            debug!("WebSocket sender task ended");
-        });
+        let ctx = SessionContext {
+            url: url.to_string(),
+            device_id: device_id.to_string(),
+            commands: self.commands.clone(),
+            incoming: self.incoming.clone(),
+            pending: self.pending.clone(),
+            offline: self.offline.clone(),
+            heartbeat: self.heartbeat.clone(),
+            auth_mode: self.auth_mode,
+            tls: self.tls.clone(),
+            config: None,
+        };
+        self.supervised = false;
+        self.worker = Some(tokio::spawn(async move {
+            run_session(ws_stream, &mut rx, &ctx).await;
+            *connected.lock().unwrap() = false;
+            debug!("WebSocket session ended");
+        }));
         
-        // Task for receiving messages
-        let device_id = device_id.to_string();
-        let tx_clone = tx.clone();
-        tokio::spawn(async move {
-            while let Some(message) = read.next().await {
-                match message {
-                    Ok(msg) => {
-                        if let Message::Text(text) = msg {
-                            debug!("Received message: {}", text);
-                            
-                            if let Ok(parsed) = serde_json::from_str::<WebSocketMessage>(&text) {
-                                match parsed.message_type {
-                                    WebSocketMessageType::Command => {
-                                        info!("Received command: {}", text);
-                                        
-                                        if let Some(id) = parsed.id {
-                                            let ack = WebSocketMessage {
-                                                message_type: WebSocketMessageType::Acknowledgement,
-                                                device_id: device_id.clone(),
-                                                payload: serde_json::json!({ "status": "received" }),
-                                                id: Some(id),
-                                                timestamp: std::time::SystemTime::now()
-                                                    .duration_since(std::time::UNIX_EPOCH)
-                                                    .unwrap_or_default()
-                                                    .as_secs(),
-                                            };
-                                            
-                                            if let Ok(ack_json) = serde_json::to_string(&ack) {
-                                                if let Err(e) = tx_clone.send(ack_json).await {
-                                                    error!("Failed to send acknowledgement: {}", e);
-                                                }
-                                            }
-                                        }
-                                    },
-                                    _ => {
-                                        debug!("Received message of type: {:?}", parsed.message_type);
-                                    }
-                                }
+        Ok(())
+    }
+
+    /// Start a supervised connection that reconnects on its own
+    ///
+    /// A background task connects with a token from `tokens`, and whenever
+    /// the socket dies it reconnects using jittered, capped backoff. A
+    /// handshake rejected with 401 drops the cached token so the next attempt
+    /// gets a new one. Messages sent while the socket is down are
+    /// queued and delivered once the connection is back.
+    pub fn start_supervised(&mut self, url: &str, tokens: Arc<TokenCache>, policy: ReconnectPolicy) {
+        self.stop_worker();
+
+        let (tx, mut rx) = mpsc::channel::<String>(100);
+        self.send_tx = Some(tx);
+
+        let connected = self.connected.clone();
+        let mut ctx = SessionContext {
+            url: url.to_string(),
+            device_id: tokens.auth_manager().device_id().to_string(),
+            commands: self.commands.clone(),
+            incoming: self.incoming.clone(),
+            pending: self.pending.clone(),
+            offline: self.offline.clone(),
+            heartbeat: self.heartbeat.clone(),
+            auth_mode: self.auth_mode,
+            tls: self.tls.clone(),
+            config: self.config.clone(),
+        };
+
+        self.supervised = true;
+        self.worker = Some(tokio::spawn(async move {
+            let mut failures = 0;
+
+            loop {
+                if let Some(updates) = ctx.config.as_mut() {
+                    if let Some(url) = updates.borrow_and_update().websocket_url.clone() {
+                        ctx.url = url;
+                    }
+                }
+
+                let result = match tokens.token().await {
+                    Ok(token) => open_stream(&ctx.url, &token, &ctx.device_id, ctx.auth_mode, ctx.tls.clone()).await,
+                    Err(e) => Err(e),
+                };
+
+                if let Err(SdkError::WebSocket(e)) = &result {
+                    if matches!(e.as_ref(), tungstenite::Error::Http(response) if response.status() == 401) {
+                        tokens.invalidate().await;
+                    }
+                }
+
+                match result {
+                    Ok(ws_stream) => {
+                        info!("WebSocket connected to {}", ctx.url);
+                        *connected.lock().unwrap() = true;
+
+                        let started = Instant::now();
+                        let end = run_session(ws_stream, &mut rx, &ctx).await;
+                        *connected.lock().unwrap() = false;
+
+                        match end {
+                            SessionEnd::ChannelClosed => break,
+                            SessionEnd::Reconfigured => continue,
+                            SessionEnd::SocketClosed => warn!("WebSocket connection lost, reconnecting"),
+                        }
+
+                        // A server that accepts and immediately closes must not
+                        // reset the backoff, or it gets reconnected to every second
+                        if started.elapsed() >= Duration::from_millis(policy.min_stable_ms) {
+                            failures = 0;
+                        } else {
+                            failures += 1;
+                            warn!("WebSocket session ended after {:?} (attempt {})", started.elapsed(), failures);
+
+                            if policy.max_attempts.is_some_and(|max| failures >= max) {
+                                error!("Giving up on WebSocket after {} attempts", failures);
+                                break;
                             }
                         }
                     },
                     Err(e) => {
-                        error!("Error receiving WebSocket message: {}", e);
-                        *connected.lock().unwrap() = false;
-                        break;
+                        failures += 1;
+                        error!("WebSocket connection attempt {} failed: {}", failures, e);
+
+                        if policy.max_attempts.is_some_and(|max| failures >= max) {
+                            error!("Giving up on WebSocket after {} attempts", failures);
+                            break;
+                        }
                     }
                 }
+
+                if rx.is_closed() {
+                    break;
+                }
+
+                let delay = backoff_with_jitter(failures, policy.base_delay_ms, policy.max_delay_ms);
+                debug!("Next WebSocket connection attempt in {:?}", delay);
+                sleep(delay).await;
             }
-            debug!("WebSocket receiver task ended");
-            *connected.lock().unwrap() = false;
-        });
-        
+
+            debug!("WebSocket supervisor ended");
+        }));
+    }
+
+    /// Abort the session or supervisor task, if one is running
+    fn stop_worker(&mut self) {
+        if let Some(handle) = self.worker.take() {
+            handle.abort();
+        }
+    }
+
+    /// Check if the connection is supervised and still reconnecting
+    pub fn is_supervised(&self) -> bool {
+        self.supervised && self.worker.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Close the connection gracefully
+    ///
+    /// Queued messages are flushed, a close frame is sent, and a supervised
+    /// connection stops reconnecting.
+    pub async fn close(&mut self) -> Result<()> {
+        // Dropping the sender makes the session flush and send a close frame
+        self.send_tx = None;
+
+        if let Some(mut handle) = self.worker.take() {
+            if !self.is_connected() {
+                handle.abort();
+            } else if timeout(CLOSE_TIMEOUT, &mut handle).await.is_err() {
+                warn!("Timed out closing WebSocket, aborting");
+                handle.abort();
+            }
+        }
+
+        *self.connected.lock().unwrap() = false;
+        info!("WebSocket closed");
         Ok(())
     }
     
@@ -179,19 +489,71 @@ impl WebSocketConnection {
     }
     
     /// Send a formatted message over the WebSocket
+    ///
+    /// A supervised connection queues the message while reconnecting;
+    /// otherwise sending fails if the socket is down. With at-least-once
+    /// delivery this resolves once the server acknowledges the message, or
+    /// once it is durably stored in the offline queue.
     async fn send_message(&self, message: WebSocketMessage) -> Result<()> {
+        let policy = match &self.delivery {
+            DeliveryGuarantee::AtMostOnce => return self.enqueue(&message).await.map(|_| ()),
+            DeliveryGuarantee::AtLeastOnce(policy) => policy,
+        };
+
+        let id = message.id.clone().unwrap_or_default();
+        let mut ack_rx = self.pending.register(&id);
+
+        for attempt in 1..=policy.max_attempts.max(1) {
+            match self.enqueue(&message).await {
+                Ok(Queued::Socket) => {},
+                Ok(Queued::Offline) => {
+                    // Durably stored counts as delivered; the queue is drained on
+                    // reconnect and that later ack is no longer tracked
+                    self.pending.remove(&id);
+                    return Ok(());
+                },
+                Err(e) => {
+                    self.pending.remove(&id);
+                    return Err(e);
+                }
+            }
+
+            match timeout(policy.ack_timeout, &mut ack_rx).await {
+                Ok(Ok(_ack)) => {
+                    debug!("Message {} acknowledged", id);
+                    return Ok(());
+                },
+                Ok(Err(_)) => break,
+                Err(_) => warn!("No acknowledgement for message {} (attempt {}), resending", id, attempt),
+            }
+        }
+
+        self.pending.remove(&id);
+        Err(SdkError::Timeout)
+    }
+
+    /// Queue a message for the sender task, or the offline queue while disconnected
+    async fn enqueue(&self, message: &WebSocketMessage) -> Result<Queued> {
+        let json = serde_json::to_string(message)?;
+
         if !self.is_connected() {
-            return Err(anyhow!("WebSocket is not connected"));
+            if let Some(queue) = &self.offline {
+                queue.push_async(json).await?;
+                debug!("Stored message {:?} in offline queue", message.id);
+                return Ok(Queued::Offline);
+            }
+
+            if !self.is_supervised() {
+                return Err(SdkError::Disconnected);
+            }
         }
         
-        let json = serde_json::to_string(&message)?;
-        
         if let Some(tx) = &self.send_tx {
             tx.send(json).await
-                .context("Failed to send message to WebSocket task")?;
-            Ok(())
+                .map_err(|_| SdkError::Disconnected)?;
+            Ok(Queued::Socket)
         } else {
-            Err(anyhow!("WebSocket sender channel not initialized"))
+            Err(SdkError::Disconnected)
         }
     }
     
@@ -203,8 +565,8 @@ impl WebSocketConnection {
     /// Reconnect with exponential backoff
     pub async fn reconnect_with_backoff(&mut self, url: &str, auth_token: &str, device_id: &str, 
                                         max_attempts: usize) -> Result<()> {
+        let defaults = ReconnectPolicy::default();
         let mut attempts = 0;
-        let mut delay = Duration::from_secs(1);
         
         while attempts < max_attempts {
             match self.connect(url, auth_token, device_id).await {
@@ -214,16 +576,278 @@ impl WebSocketConnection {
                     error!("Reconnection attempt {} failed: {}", attempts, e);
                     
                     if attempts >= max_attempts {
-                        return Err(anyhow!("Failed to reconnect after {} attempts", max_attempts));
+                        return Err(e);
                     }
                     
-                    sleep(delay).await;
-                    delay = delay.saturating_mul(2); // Exponential backoff
+                    sleep(backoff_with_jitter(attempts - 1, defaults.base_delay_ms, defaults.max_delay_ms)).await;
                 }
             }
         }
         
-        Err(anyhow!("Failed to reconnect"))
+        Err(SdkError::Disconnected)
+    }
+}
+
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        self.stop_worker();
+    }
+}
+
+/// Open a WebSocket stream authenticated with the given token
+async fn open_stream(
+    url: &str,
+    auth_token: &str,
+    device_id: &str,
+    mode: WebSocketAuthMode,
+    tls: Option<Arc<rustls::ClientConfig>>,
+) -> Result<WsStream> {
+    let mut url = Url::parse(url)
+        .map_err(|e| SdkError::Config(format!("Invalid WebSocket URL {}: {}", url, e)))?;
+
+    url.query_pairs_mut().append_pair("device_id", device_id);
+    if mode == WebSocketAuthMode::QueryParam {
+        url.query_pairs_mut().append_pair("token", auth_token);
+    }
+
+    let mut request = url.as_str().into_client_request()?;
+    let headers = request.headers_mut();
+
+    match mode {
+        WebSocketAuthMode::Header => {
+            let value = HeaderValue::from_str(&format!("Bearer {}", auth_token))
+                .map_err(|_| SdkError::Auth("Token is not a valid header value".to_string()))?;
+            headers.insert(header::AUTHORIZATION, value);
+        },
+        WebSocketAuthMode::Subprotocol => {
+            // Subprotocol names only allow token characters, so re-encode as base64url
+            let encoded = general_purpose::URL_SAFE_NO_PAD.encode(auth_token);
+            let value = HeaderValue::from_str(&format!("{}, {}{}", SUBPROTOCOL, SUBPROTOCOL_TOKEN_PREFIX, encoded))
+                .map_err(|_| SdkError::Auth("Token is not a valid header value".to_string()))?;
+            headers.insert(header::SEC_WEBSOCKET_PROTOCOL, value);
+        },
+        WebSocketAuthMode::QueryParam => {},
+    }
+
+    let (ws_stream, _) = connect_async_tls_with_config(request, None, false, tls.map(Connector::Rustls)).await?;
+    Ok(ws_stream)
+}
+
+/// Pump outgoing messages and handle incoming ones until the socket or the channel closes
+async fn run_session(ws_stream: WsStream, rx: &mut mpsc::Receiver<String>, ctx: &SessionContext) -> SessionEnd {
+    let (mut write, mut read) = ws_stream.split();
+
+    if let Some(queue) = &ctx.offline {
+        if !drain_offline(queue, &mut write).await {
+            return SessionEnd::SocketClosed;
+        }
+    }
+
+    // Acknowledgements from command handlers running on their own tasks
+    let (ack_tx, mut ack_rx) = mpsc::unbounded_channel::<String>();
+
+    let mut updates = ctx.config.clone();
+
+    let heartbeat = ctx.heartbeat.clone();
+    let mut next_ping = heartbeat.as_ref().map(|hb| Instant::now() + hb.ping_interval);
+    let mut pong_deadline: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            _ = sleep_until(next_ping.unwrap_or_else(far_future)), if next_ping.is_some() => {
+                let hb = heartbeat.as_ref().unwrap();
+                if let Err(e) = write.send(Message::Ping(Vec::new().into())).await {
+                    error!("Error sending ping: {}", e);
+                    return SessionEnd::SocketClosed;
+                }
+                if pong_deadline.is_none() {
+                    pong_deadline = Some(Instant::now() + hb.pong_timeout);
+                }
+                next_ping = Some(Instant::now() + hb.ping_interval);
+            },
+            _ = sleep_until(pong_deadline.unwrap_or_else(far_future)), if pong_deadline.is_some() => {
+                warn!("No pong received in time, treating connection as dead");
+                return SessionEnd::SocketClosed;
+            },
+            outgoing = rx.recv() => match outgoing {
+                Some(message) => {
+                    if let Err(e) = write.send(Message::Text(message.clone().into())).await {
+                        error!("Error sending WebSocket message: {}", e);
+                        // Keep the message for the next connection rather than losing it;
+                        // the queue skips an at-least-once resend of the same ID
+                        if let Some(queue) = &ctx.offline {
+                            if let Err(e) = queue.push_async(message).await {
+                                error!("Failed to store message in offline queue: {}", e);
+                            }
+                        }
+                        return SessionEnd::SocketClosed;
+                    }
+                },
+                None => {
+                    let _ = write.close().await;
+                    return SessionEnd::ChannelClosed;
+                }
+            },
+            url = url_change(&mut updates, &ctx.url) => {
+                info!("WebSocket URL changed to {}, reconnecting", url);
+                let _ = write.close().await;
+                return SessionEnd::Reconfigured;
+            },
+            Some(ack) = ack_rx.recv() => {
+                if let Err(e) = write.send(Message::Text(ack.into())).await {
+                    error!("Failed to send acknowledgement: {}", e);
+                    return SessionEnd::SocketClosed;
+                }
+            },
+            incoming = read.next() => match incoming {
+                Some(Ok(Message::Text(text))) => handle_text(&text, ctx, &ack_tx),
+                Some(Ok(Message::Pong(_))) => pong_deadline = None,
+                Some(Ok(Message::Ping(_))) => {
+                    // The pong reply is queued by tungstenite and goes out on flush
+                    if let Err(e) = write.flush().await {
+                        error!("Error replying to ping: {}", e);
+                        return SessionEnd::SocketClosed;
+                    }
+                },
+                Some(Ok(Message::Close(frame))) => {
+                    log_close_frame(frame.as_ref());
+                    let _ = write.close().await;
+                    return SessionEnd::SocketClosed;
+                },
+                Some(Ok(_)) => {},
+                Some(Err(e)) => {
+                    error!("Error receiving WebSocket message: {}", e);
+                    return SessionEnd::SocketClosed;
+                },
+                None => return SessionEnd::SocketClosed,
+            }
+        }
+    }
+}
+
+/// Wait until the configured WebSocket URL differs from `current`
+///
+/// Never completes without configuration updates.
+async fn url_change(updates: &mut Option<watch::Receiver<Arc<ClientConfig>>>, current: &str) -> String {
+    let Some(updates) = updates else {
+        return std::future::pending().await;
+    };
+
+    loop {
+        if updates.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+        if let Some(url) = updates.borrow_and_update().websocket_url.as_deref() {
+            if url != current {
+                return url.to_string();
+            }
+        }
+    }
+}
+
+/// Log why the server closed the connection
+fn log_close_frame(frame: Option<&CloseFrame>) {
+    match frame {
+        Some(frame) => info!("Server closed WebSocket with code {}: {}", u16::from(frame.code), frame.reason),
+        None => info!("Server closed WebSocket without a close code"),
+    }
+}
+
+/// An instant far enough away to never fire, for disabled timers
+fn far_future() -> Instant {
+    Instant::now() + Duration::from_secs(86_400 * 365)
+}
+
+/// Send everything in the offline queue, oldest first
+///
+/// Returns `false` if the socket failed while draining.
+async fn drain_offline(queue: &Arc<OfflineQueue>, write: &mut SplitSink<WsStream, Message>) -> bool {
+    loop {
+        let segment = match queue.oldest_async().await {
+            Ok(Some(segment)) => segment,
+            Ok(None) => return true,
+            Err(e) => {
+                error!("Failed to read offline queue: {}", e);
+                return true;
+            }
+        };
+
+        debug!("Draining {} messages from offline queue", segment.messages.len());
+
+        for message in &segment.messages {
+            if let Err(e) = write.send(Message::Text(message.clone().into())).await {
+                error!("Error sending queued message: {}", e);
+                return false;
+            }
+        }
+
+        if let Err(e) = queue.remove_async(segment).await {
+            error!("Failed to remove drained offline segment: {}", e);
+            return true;
+        }
+    }
+}
+
+/// Handle an incoming text frame
+///
+/// Commands are dispatched to the registry on a separate task; the handler's
+/// response is queued on `ack_tx` as the payload of the acknowledgement.
+fn handle_text(text: &str, ctx: &SessionContext, ack_tx: &mpsc::UnboundedSender<String>) {
+    debug!("Received message: {}", text);
+
+    let Ok(parsed) = serde_json::from_str::<WebSocketMessage>(text) else {
+        warn!("Ignoring malformed WebSocket message");
+        return;
+    };
+
+    // Nobody may be subscribed, which is fine
+    let _ = ctx.incoming.send(parsed.clone());
+
+    match parsed.message_type {
+        WebSocketMessageType::Command => {
+            info!("Received command: {}", text);
+
+            let commands = ctx.commands.clone();
+            let device_id = ctx.device_id.clone();
+            let ack_tx = ack_tx.clone();
+
+            tokio::spawn(async move {
+                let response = match serde_json::from_value::<CommandRequest>(parsed.payload) {
+                    Ok(request) => commands.dispatch(request).await,
+                    Err(e) => CommandResponse::failure(format!("Invalid command payload: {}", e)),
+                };
+
+                let Some(id) = parsed.id else {
+                    return;
+                };
+
+                let ack = WebSocketMessage {
+                    message_type: WebSocketMessageType::Acknowledgement,
+                    device_id,
+                    payload: serde_json::to_value(&response).unwrap_or_default(),
+                    id: Some(id),
+                    timestamp: std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                };
+
+                if let Ok(ack_json) = serde_json::to_string(&ack) {
+                    // The session may have ended while the handler ran
+                    if ack_tx.send(ack_json).is_err() {
+                        warn!("Connection closed before acknowledging command {}", ack.id.unwrap_or_default());
+                    }
+                }
+            });
+        },
+        WebSocketMessageType::Acknowledgement => {
+            if !ctx.pending.complete(&parsed) {
+                debug!("Received acknowledgement for untracked message {:?}", parsed.id);
+            }
+        },
+        _ => {
+            debug!("Received message of type: {:?}", parsed.message_type);
+        }
     }
 }
 
@@ -232,4 +856,4 @@ fn generate_message_id() -> String {
     let mut rng = ThreadRng::default();
     let random_bytes: [u8; 8] = rng.random();
     general_purpose::STANDARD.encode(random_bytes)
-}
\ No newline at end of file
+}
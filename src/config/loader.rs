@@ -0,0 +1,187 @@
+//! Layered loading of [`ClientConfig`]
+//!
+//! Sources are merged in order, later ones winning: built-in defaults,
+//! configuration files (TOML, YAML or JSON, chosen by extension and JSON
+//! when the extension is not recognized), the
+//! selected profile's files, `IOT_SDK_*` environment variables and finally
+//! programmatic overrides.
+
+use config::{Config, ConfigError, Environment, File, FileFormat, FileSourceFile, Value};
+use std::path::{Path, PathBuf};
+use crate::config::ClientConfig;
+use crate::error::{Result, SdkError};
+
+/// Default prefix of environment variables
+pub const DEFAULT_ENV_PREFIX: &str = "IOT_SDK";
+
+/// Keys holding lists, given as comma-separated environment variables
+const LIST_KEYS: &[&str] = &["tls.pinned_spki_sha256"];
+
+/// Builds a [`ClientConfig`] from layered sources
+///
+/// Environment variables are named after the key with the prefix and `__`
+/// between nested keys, e.g. `IOT_SDK_API_URL` or
+/// `IOT_SDK_OFFLINE_QUEUE__DIRECTORY`.
+///
+/// A profile such as `prod` adds `<name>.prod.<ext>` next to every
+/// configuration file `<name>.<ext>`. It is set with
+/// [`ConfigLoader::with_profile`] or the `IOT_SDK_PROFILE` variable.
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    files: Vec<PathBuf>,
+    profile: Option<String>,
+    env_prefix: Option<String>,
+    overrides: Vec<(String, Value)>,
+}
+
+impl Default for ConfigLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigLoader {
+    /// Create a loader reading `IOT_SDK_*` environment variables
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            profile: None,
+            env_prefix: Some(DEFAULT_ENV_PREFIX.to_string()),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Add a configuration file; its format is taken from the extension,
+    /// falling back to JSON
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Select a profile such as `dev`, `staging` or `prod`
+    pub fn with_profile<S: Into<String>>(mut self, profile: S) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Read environment variables with a different prefix
+    pub fn with_env_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Ignore environment variables
+    pub fn without_env(mut self) -> Self {
+        self.env_prefix = None;
+        self
+    }
+
+    /// Set a key, overriding every other source
+    ///
+    /// Nested keys are separated by dots, e.g. `tls.min_version`.
+    pub fn set_override<K: Into<String>, V: Into<Value>>(mut self, key: K, value: V) -> Self {
+        self.overrides.push((key.into(), value.into()));
+        self
+    }
+
+    /// Merge all sources into a configuration
+    ///
+    /// Errors name the offending key and the source it came from.
+    pub fn load(&self) -> Result<ClientConfig> {
+        let mut builder = Config::builder()
+            .set_default("request_timeout", 30)
+            .map_err(config_error)?;
+
+        for path in &self.files {
+            builder = builder.add_source(file_source(path));
+        }
+
+        if let Some(profile) = self.profile() {
+            let profile_files: Vec<PathBuf> = self.files.iter()
+                .map(|path| profile_path(path, &profile))
+                .collect();
+
+            if !self.files.is_empty() && !profile_files.iter().any(|path| path.exists()) {
+                return Err(SdkError::Config(format!("No configuration files found for profile {}", profile)));
+            }
+
+            for path in profile_files {
+                builder = builder.add_source(file_source(&path).required(false));
+            }
+        }
+
+        if let Some(prefix) = &self.env_prefix {
+            builder = builder.add_source(
+                Environment::with_prefix(prefix)
+                    .prefix_separator("_")
+                    .separator("__"),
+            );
+
+            for key in LIST_KEYS {
+                if let Ok(value) = std::env::var(env_var_name(prefix, key)) {
+                    let items: Vec<String> = value.split(',')
+                        .map(|item| item.trim().to_string())
+                        .filter(|item| !item.is_empty())
+                        .collect();
+                    builder = builder.set_override(*key, items).map_err(config_error)?;
+                }
+            }
+        }
+
+        for (key, value) in &self.overrides {
+            builder = builder.set_override(key.as_str(), value.clone()).map_err(config_error)?;
+        }
+
+        builder.build()
+            .and_then(|config| config.try_deserialize::<ClientConfig>())
+            .map_err(config_error)
+    }
+
+    /// Files whose changes should trigger a reload
+    pub(crate) fn watched_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self.files.clone();
+        if let Some(profile) = self.profile() {
+            paths.extend(self.files.iter().map(|path| profile_path(path, &profile)));
+        }
+        paths
+    }
+
+    /// Profile chosen programmatically or through the environment
+    fn profile(&self) -> Option<String> {
+        self.profile.clone().or_else(|| {
+            let prefix = self.env_prefix.as_deref()?;
+            std::env::var(env_var_name(prefix, "profile")).ok()
+        })
+    }
+}
+
+/// `config.toml` with profile `prod` becomes `config.prod.toml`
+fn profile_path(path: &Path, profile: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, profile, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, profile),
+    };
+    path.with_file_name(name)
+}
+
+/// File source whose format follows the extension, defaulting to JSON
+fn file_source(path: &Path) -> File<FileSourceFile, FileFormat> {
+    let extension = path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let format = match extension.as_deref() {
+        Some("toml") => FileFormat::Toml,
+        Some("yaml" | "yml") => FileFormat::Yaml,
+        _ => FileFormat::Json,
+    };
+    File::from(path).format(format)
+}
+
+/// Environment variable for a key, e.g. `tls.min_version` becomes `IOT_SDK_TLS__MIN_VERSION`
+fn env_var_name(prefix: &str, key: &str) -> String {
+    format!("{}_{}", prefix, key.replace('.', "__")).to_uppercase()
+}
+
+fn config_error(error: ConfigError) -> SdkError {
+    SdkError::Config(format!("Invalid configuration: {}", error))
+}
@@ -1,11 +1,25 @@
 //! Configuration for the IoT SDK client
 
+pub mod loader;
+pub mod validation;
+pub mod watcher;
+
 use serde::{Deserialize, Serialize};
 use std::path::Path;
-use anyhow::{Result, Context};
+use crate::auth::{AuthFlow, TokenFormat};
+use crate::communication::breaker::CircuitBreakerConfig;
+use crate::communication::offline::OfflineQueueConfig;
+use crate::communication::retry::RetryPolicy;
+use crate::communication::tls::TlsConfig;
+use crate::communication::websocket::WebSocketAuthMode;
+use crate::error::Result;
+
+pub use loader::ConfigLoader;
+pub use validation::{ConfigIssue, ValidationError};
+pub use watcher::ConfigWatcher;
 
 /// Configuration for the IoT client
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct ClientConfig {
     /// Base URL for the API service
     pub api_url: String,
@@ -13,17 +27,92 @@ pub struct ClientConfig {
     /// Path to the private key file for authentication
     pub private_key_path: Option<String>,
     
+    /// Passphrase of an encrypted private key file
+    #[serde(default, skip_serializing)]
+    pub private_key_passphrase: Option<String>,
+
+    /// Environment variable holding the passphrase of an encrypted private key file
+    pub private_key_passphrase_env: Option<String>,
+
     /// Private key as a base64 string (alternative to file)
     pub private_key_base64: Option<String>,
+
+    /// Environment variable holding the private key as a base64 string
+    pub private_key_env: Option<String>,
     
     /// Device identifier
     pub device_id: Option<String>,
+
+    /// Directory where a generated key pair and device ID are persisted
+    /// when no private key is configured
+    pub identity_dir: Option<String>,
+
+    /// How auth tokens are obtained
+    #[serde(default)]
+    pub auth_flow: AuthFlow,
+
+    /// Format of the auth tokens sent to the service
+    #[serde(default)]
+    pub token_format: TokenFormat,
+
+    /// Audience (`aud` claim) of JWT tokens
+    pub token_audience: Option<String>,
+
+    /// Lifetime of auth tokens in seconds
+    pub token_lifetime: Option<u64>,
+
+    /// Fraction of a token's lifetime after which it is replaced (0.0 to 1.0)
+    pub token_refresh_fraction: Option<f64>,
     
+    /// TLS settings for the HTTP and WebSocket connections
+    pub tls: Option<TlsConfig>,
+
     /// Timeout for API requests in seconds
     pub request_timeout: Option<u64>,
+
+    /// Retry settings for failed API requests
+    pub http_retry: Option<RetryPolicy>,
+
+    /// Circuit breaker settings for API requests
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
     
-    /// WebSocket endpoint URL
+    /// WebSocket endpoint URL, derived from `api_url` by
+    /// [`ClientConfig::normalize`] when unset
     pub websocket_url: Option<String>,
+
+    /// How the auth token is sent on the WebSocket handshake
+    #[serde(default)]
+    pub websocket_auth: WebSocketAuthMode,
+
+    /// On-disk queue for messages sent while the WebSocket is down
+    pub offline_queue: Option<OfflineQueueConfig>,
+}
+
+impl std::fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("api_url", &self.api_url)
+            .field("private_key_path", &self.private_key_path)
+            .field("private_key_passphrase", &self.private_key_passphrase.as_ref().map(|_| "<redacted>"))
+            .field("private_key_passphrase_env", &self.private_key_passphrase_env)
+            .field("private_key_base64", &self.private_key_base64)
+            .field("private_key_env", &self.private_key_env)
+            .field("device_id", &self.device_id)
+            .field("identity_dir", &self.identity_dir)
+            .field("auth_flow", &self.auth_flow)
+            .field("token_format", &self.token_format)
+            .field("token_audience", &self.token_audience)
+            .field("token_lifetime", &self.token_lifetime)
+            .field("token_refresh_fraction", &self.token_refresh_fraction)
+            .field("tls", &self.tls)
+            .field("request_timeout", &self.request_timeout)
+            .field("http_retry", &self.http_retry)
+            .field("circuit_breaker", &self.circuit_breaker)
+            .field("websocket_url", &self.websocket_url)
+            .field("websocket_auth", &self.websocket_auth)
+            .field("offline_queue", &self.offline_queue)
+            .finish()
+    }
 }
 
 impl ClientConfig {
@@ -32,22 +121,36 @@ impl ClientConfig {
         Self {
             api_url: api_url.to_string(),
             private_key_path: None,
+            private_key_passphrase: None,
+            private_key_passphrase_env: None,
             private_key_base64: None,
+            private_key_env: None,
             device_id: None,
+            identity_dir: None,
+            auth_flow: AuthFlow::default(),
+            token_format: TokenFormat::default(),
+            token_audience: None,
+            token_lifetime: None,
+            token_refresh_fraction: None,
+            tls: None,
             request_timeout: Some(30),
+            http_retry: None,
+            circuit_breaker: None,
             websocket_url: None,
+            websocket_auth: WebSocketAuthMode::default(),
+            offline_queue: None,
         }
     }
     
-    /// Load configuration from a file
+    /// Load configuration from a TOML, YAML or JSON file
+    ///
+    /// Only the file is read; use [`ConfigLoader`] to layer profiles and
+    /// environment variables on top.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let config_str = std::fs::read_to_string(path)
-            .context("Failed to read config file")?;
-            
-        let config: ClientConfig = serde_json::from_str(&config_str)
-            .context("Failed to parse config file")?;
-            
-        Ok(config)
+        ConfigLoader::new()
+            .without_env()
+            .with_file(path)
+            .load()
     }
     
     /// Set the private key from a file path
@@ -56,21 +159,95 @@ impl ClientConfig {
         self
     }
     
+    /// Set the passphrase of an encrypted private key file
+    pub fn with_private_key_passphrase<S: Into<String>>(mut self, passphrase: S) -> Self {
+        self.private_key_passphrase = Some(passphrase.into());
+        self
+    }
+
+    /// Read the passphrase of an encrypted private key file from an environment variable
+    pub fn with_private_key_passphrase_env<S: Into<String>>(mut self, var: S) -> Self {
+        self.private_key_passphrase_env = Some(var.into());
+        self
+    }
+
     /// Set the private key from a base64 encoded string
     pub fn with_private_key_base64<S: Into<String>>(mut self, key: S) -> Self {
         self.private_key_base64 = Some(key.into());
         self
     }
     
+    /// Read the private key from an environment variable at startup
+    pub fn with_private_key_env<S: Into<String>>(mut self, var: S) -> Self {
+        self.private_key_env = Some(var.into());
+        self
+    }
+
     /// Set the device ID
     pub fn with_device_id<S: Into<String>>(mut self, device_id: S) -> Self {
         self.device_id = Some(device_id.into());
         self
     }
     
+    /// Persist the generated identity in a directory
+    pub fn with_identity_dir<S: Into<String>>(mut self, dir: S) -> Self {
+        self.identity_dir = Some(dir.into());
+        self
+    }
+
+    /// Log in with a challenge-response exchange and use server-issued session tokens
+    pub fn with_challenge_response_auth(mut self) -> Self {
+        self.auth_flow = AuthFlow::ChallengeResponse;
+        self
+    }
+
+    /// Mint standard EdDSA JWT tokens, optionally for the given audience
+    pub fn with_jwt_tokens(mut self, audience: Option<&str>) -> Self {
+        self.token_format = TokenFormat::Jwt;
+        self.token_audience = audience.map(str::to_string);
+        self
+    }
+
+    /// Set how long auth tokens live and when the cached token is replaced
+    pub fn with_token_lifetime(mut self, seconds: u64, refresh_fraction: f64) -> Self {
+        self.token_lifetime = Some(seconds);
+        self.token_refresh_fraction = Some(refresh_fraction);
+        self
+    }
+
+    /// Set the TLS settings for the HTTP and WebSocket connections
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Set how failed API requests are retried
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.http_retry = Some(retry);
+        self
+    }
+
+    /// Set when the circuit breaker stops requests to a failing API
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
     /// Set the websocket URL
     pub fn with_websocket_url<S: Into<String>>(mut self, url: S) -> Self {
         self.websocket_url = Some(url.into());
         self
     }
+
+    /// Set how the auth token is sent on the WebSocket handshake
+    pub fn with_websocket_auth(mut self, mode: WebSocketAuthMode) -> Self {
+        self.websocket_auth = mode;
+        self
+    }
+
+    /// Buffer outgoing messages on disk while offline
+    pub fn with_offline_queue(mut self, queue: OfflineQueueConfig) -> Self {
+        self.offline_queue = Some(queue);
+        self
+    }
 }
\ No newline at end of file
@@ -0,0 +1,228 @@
+//! Validation and normalization of [`ClientConfig`]
+
+use std::fmt;
+use thiserror::Error;
+use url::Url;
+use crate::communication::tls::decode_pin;
+use crate::config::ClientConfig;
+
+/// Path appended to `api_url` when deriving the WebSocket URL
+pub const DEFAULT_WEBSOCKET_PATH: &str = "/ws";
+
+/// A single problem found in a configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Path of the offending field, e.g. `tls.client_key_path`
+    pub field: String,
+
+    /// What is wrong and how to fix it
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Every problem found by [`ClientConfig::validate`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub struct ValidationError {
+    /// The problems, in field order
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let issues: Vec<String> = self.issues.iter().map(ConfigIssue::to_string).collect();
+        f.write_str(&issues.join("; "))
+    }
+}
+
+impl ValidationError {
+    /// Whether a problem was reported for `field`
+    pub fn has_issue(&self, field: &str) -> bool {
+        self.issues.iter().any(|issue| issue.field == field)
+    }
+}
+
+/// Collects issues while walking a configuration
+#[derive(Default)]
+struct Issues(Vec<ConfigIssue>);
+
+impl Issues {
+    fn add<F: Into<String>, M: Into<String>>(&mut self, field: F, message: M) {
+        self.0.push(ConfigIssue { field: field.into(), message: message.into() });
+    }
+}
+
+impl ClientConfig {
+    /// Check the configuration for values the client can't work with
+    ///
+    /// Every problem is reported at once, each with the path of its field.
+    /// Run [`ClientConfig::normalize`] first to accept URLs with trailing
+    /// slashes or surrounding whitespace.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        let mut issues = Issues::default();
+
+        check_url(&mut issues, "api_url", &self.api_url, &["http", "https"]);
+
+        let key_sources: Vec<&str> = [
+            ("private_key_path", self.private_key_path.is_some()),
+            ("private_key_base64", self.private_key_base64.is_some()),
+            ("private_key_env", self.private_key_env.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(field, set)| set.then_some(field))
+        .collect();
+        for field in key_sources.iter().skip(1) {
+            issues.add(*field, format!("conflicts with {}; set only one private key source", key_sources[0]));
+        }
+
+        let passphrases = [
+            ("private_key_passphrase", self.private_key_passphrase.is_some()),
+            ("private_key_passphrase_env", self.private_key_passphrase_env.is_some()),
+        ];
+        for (field, set) in passphrases {
+            if set && self.private_key_path.is_none() {
+                issues.add(field, "only applies to an encrypted private_key_path, which is not set");
+            }
+        }
+        if self.private_key_passphrase.is_some() && self.private_key_passphrase_env.is_some() {
+            issues.add("private_key_passphrase_env", "conflicts with private_key_passphrase; set only one");
+        }
+
+        if self.token_lifetime == Some(0) {
+            issues.add("token_lifetime", "must be greater than 0 seconds");
+        }
+        if let Some(fraction) = self.token_refresh_fraction {
+            if !(0.0..=1.0).contains(&fraction) {
+                issues.add("token_refresh_fraction", format!("must be between 0.0 and 1.0, got {}", fraction));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            match (&tls.client_cert_path, &tls.client_key_path) {
+                (Some(_), None) => issues.add("tls.client_key_path", "is required when tls.client_cert_path is set"),
+                (None, Some(_)) => issues.add("tls.client_cert_path", "is required when tls.client_key_path is set"),
+                _ => {},
+            }
+            for (i, pin) in tls.pinned_spki_sha256.iter().enumerate() {
+                if decode_pin(pin).is_err() {
+                    issues.add(format!("tls.pinned_spki_sha256[{}]", i), "must be a base64 SHA-256 hash");
+                }
+            }
+        }
+
+        if self.request_timeout == Some(0) {
+            issues.add("request_timeout", "must be greater than 0 seconds");
+        }
+
+        if let Some(retry) = &self.http_retry {
+            if retry.max_attempts == 0 {
+                issues.add("http_retry.max_attempts", "must be at least 1");
+            }
+            if retry.max_delay_ms < retry.base_delay_ms {
+                issues.add("http_retry.max_delay_ms", "must be at least http_retry.base_delay_ms");
+            }
+        }
+
+        if let Some(breaker) = &self.circuit_breaker {
+            if !(breaker.failure_threshold > 0.0 && breaker.failure_threshold <= 1.0) {
+                issues.add(
+                    "circuit_breaker.failure_threshold",
+                    format!("must be above 0.0 and at most 1.0, got {}", breaker.failure_threshold),
+                );
+            }
+            if breaker.minimum_requests == 0 {
+                issues.add("circuit_breaker.minimum_requests", "must be at least 1");
+            }
+            if breaker.half_open_max_requests == 0 {
+                issues.add("circuit_breaker.half_open_max_requests", "must be at least 1");
+            }
+        }
+
+        if let Some(url) = &self.websocket_url {
+            check_url(&mut issues, "websocket_url", url, &["ws", "wss"]);
+        }
+
+        if let Some(queue) = &self.offline_queue {
+            if queue.directory.trim().is_empty() {
+                issues.add("offline_queue.directory", "must not be empty");
+            }
+            if queue.max_segment_bytes == 0 {
+                issues.add("offline_queue.max_segment_bytes", "must be greater than 0");
+            }
+            if queue.max_total_bytes < queue.max_segment_bytes {
+                issues.add("offline_queue.max_total_bytes", "must be at least offline_queue.max_segment_bytes");
+            }
+        }
+
+        if issues.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { issues: issues.0 })
+        }
+    }
+
+    /// Tidy up URLs
+    ///
+    /// Surrounding whitespace and trailing slashes are removed. Without a
+    /// `websocket_url`, one is derived from `api_url`: `https://host/v1`
+    /// becomes `wss://host/v1/ws`, `http://` becomes `ws://`.
+    pub fn normalize(&mut self) {
+        self.api_url = normalize_url(&self.api_url);
+
+        self.websocket_url = match self.websocket_url.take() {
+            Some(url) => Some(normalize_url(&url)),
+            None => websocket_url_for(&self.api_url),
+        };
+    }
+
+    /// Normalize and validate the configuration
+    pub fn validated(mut self) -> std::result::Result<Self, ValidationError> {
+        self.normalize();
+        self.validate()?;
+        Ok(self)
+    }
+}
+
+fn check_url(issues: &mut Issues, field: &str, url: &str, schemes: &[&str]) {
+    let expected = schemes.iter()
+        .map(|scheme| format!("{}://", scheme))
+        .collect::<Vec<_>>()
+        .join(" or ");
+
+    match Url::parse(url) {
+        Ok(parsed) if !schemes.contains(&parsed.scheme()) => {
+            issues.add(field, format!("{} uses {}://; expected {}", url, parsed.scheme(), expected));
+        },
+        Ok(parsed) if parsed.host_str().is_none() => {
+            issues.add(field, format!("{} has no host", url));
+        },
+        Ok(_) => {},
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            issues.add(field, format!("{:?} has no scheme; expected an absolute URL starting with {}", url, expected));
+        },
+        Err(e) => issues.add(field, format!("{:?} is not a valid URL: {}", url, e)),
+    }
+}
+
+fn normalize_url(url: &str) -> String {
+    url.trim().trim_end_matches('/').to_string()
+}
+
+/// WebSocket URL served next to an API URL, if the API URL is usable
+fn websocket_url_for(api_url: &str) -> Option<String> {
+    let mut url = Url::parse(api_url).ok()?;
+    let scheme = match url.scheme() {
+        "https" => "wss",
+        "http" => "ws",
+        _ => return None,
+    };
+    url.set_scheme(scheme).ok()?;
+
+    let path = format!("{}{}", url.path().trim_end_matches('/'), DEFAULT_WEBSOCKET_PATH);
+    url.set_path(&path);
+    Some(url.to_string())
+}
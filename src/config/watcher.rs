@@ -0,0 +1,123 @@
+//! Hot reloading of [`ClientConfig`]
+//!
+//! A [`ConfigWatcher`] re-reads its [`ConfigLoader`] when a configuration file
+//! changes or the server sends a reload command, and publishes every valid
+//! new configuration on a watch channel. Invalid configurations are logged
+//! and ignored, so the running one stays in place.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+use crate::communication::commands::CommandRegistry;
+use crate::config::{ClientConfig, ConfigLoader};
+use crate::error::Result;
+
+/// Name of the command that makes the device reload its configuration
+pub const RELOAD_COMMAND: &str = "config.reload";
+
+/// Reloads configuration and publishes changes
+///
+/// Cloning is cheap; clones share the same channel.
+#[derive(Clone)]
+pub struct ConfigWatcher {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    loader: ConfigLoader,
+    tx: watch::Sender<Arc<ClientConfig>>,
+}
+
+impl ConfigWatcher {
+    /// Load the initial configuration
+    pub fn new(loader: ConfigLoader) -> Result<Self> {
+        let config = loader.load()?.validated()?;
+
+        let (tx, _) = watch::channel(Arc::new(config));
+        Ok(Self { inner: Arc::new(Inner { loader, tx }) })
+    }
+
+    /// Current configuration
+    pub fn current(&self) -> Arc<ClientConfig> {
+        self.inner.tx.borrow().clone()
+    }
+
+    /// Receive every new configuration
+    pub fn subscribe(&self) -> watch::Receiver<Arc<ClientConfig>> {
+        self.inner.tx.subscribe()
+    }
+
+    /// Re-read the configuration sources
+    ///
+    /// Returns whether the configuration changed. An invalid configuration
+    /// is rejected with an error and not published.
+    pub fn reload(&self) -> Result<bool> {
+        let config = self.inner.loader.load()?.validated()?;
+
+        let changed = self.inner.tx.send_if_modified(|current| {
+            if **current == config {
+                false
+            } else {
+                *current = Arc::new(config);
+                true
+            }
+        });
+
+        if changed {
+            info!("Configuration reloaded");
+        }
+        Ok(changed)
+    }
+
+    /// Reload whenever one of the configuration files changes
+    ///
+    /// Files are polled every `interval`. The task runs until aborted.
+    pub fn watch_files(&self, interval: Duration) -> JoinHandle<()> {
+        let watcher = self.clone();
+
+        tokio::spawn(async move {
+            let paths = watcher.inner.loader.watched_paths();
+            let mut seen = modification_times(&paths);
+
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let current = modification_times(&paths);
+                if current == seen {
+                    continue;
+                }
+                seen = current;
+
+                debug!("Configuration files changed, reloading");
+                if let Err(e) = watcher.reload() {
+                    error!("Ignoring invalid configuration: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Register the [`RELOAD_COMMAND`] handler, so the server can trigger a reload
+    pub fn register_commands(&self, commands: &CommandRegistry) {
+        let watcher = self.clone();
+        commands.register(RELOAD_COMMAND, move |_: serde_json::Value| {
+            let watcher = watcher.clone();
+            async move {
+                watcher.reload().map(|changed| serde_json::json!({ "changed": changed }))
+            }
+        });
+    }
+}
+
+/// Modification time of each file, `None` if it doesn't exist
+fn modification_times(paths: &[PathBuf]) -> HashMap<PathBuf, Option<SystemTime>> {
+    paths.iter()
+        .map(|path| {
+            let modified = std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+            (path.clone(), modified)
+        })
+        .collect()
+}
@@ -1,9 +1,10 @@
 //! Device Management ad metadata handling
-use anyhow::Result;
+use crate::error::Result;
 use serde::{Serialize, Deserialize};
 use crate::models::{DeviceInfo, DeviceStatus};
 use crate::communication::http::HttpClient;
 use std::collections::HashMap;
+use std::time::Duration;
 use tracing::info;
 
 /// Device manager for handling IoT Devices
@@ -34,7 +35,32 @@ impl DeviceManager {
     }
 
     /// Register a new deivce
+    ///
+    /// Retries carry the same idempotency key, so the server can drop
+    /// duplicate registrations.
     pub async fn register_device(&self, device_id: &str, info: &DeviceInfo) -> Result<DeviceRegistrationResponse> {
+        self.register(device_id, info, None).await
+    }
+
+    /// Register a new device with a caller-chosen idempotency key
+    ///
+    /// Reusing the key across application retries or restarts lets the
+    /// server recognize a registration it has already processed.
+    pub async fn register_device_with_idempotency_key(
+        &self,
+        device_id: &str,
+        info: &DeviceInfo,
+        idempotency_key: &str,
+    ) -> Result<DeviceRegistrationResponse> {
+        self.register(device_id, info, Some(idempotency_key)).await
+    }
+
+    async fn register(
+        &self,
+        device_id: &str,
+        info: &DeviceInfo,
+        idempotency_key: Option<&str>,
+    ) -> Result<DeviceRegistrationResponse> {
         let path = "/devices";
         let payload = serde_json::json!({
             "device_id": device_id,
@@ -45,7 +71,10 @@ impl DeviceManager {
         });
 
         info!("Registering device {}", device_id);
-        let response: DeviceRegistrationResponse = self.http_client.post(path, &payload).await?;
+        let response: DeviceRegistrationResponse = match idempotency_key {
+            Some(key) => self.http_client.post_with_idempotency_key(path, &payload, key).await?,
+            None => self.http_client.post(path, &payload).await?,
+        };
         info!("Device registered successfully: {}", device_id);
 
         Ok(response)
@@ -78,6 +107,25 @@ impl DeviceManager {
         Ok(())
     }
 
+    /// Rotate this device's key
+    ///
+    /// Generates a new key, registers it with `POST /devices/{id}/keys` using a
+    /// request signed by the old key, then persists and switches to the new key.
+    /// The old key stays valid for `grace_period`. Returns the new key ID.
+    pub async fn rotate_key(&self, grace_period: Duration) -> Result<String> {
+        let auth = self.http_client.auth_manager();
+        let rotation = auth.begin_key_rotation(grace_period)?;
+
+        let path = format!("/devices/{}/keys", auth.device_id());
+        info!("Registering new key {} for device {}", rotation.key_id(), auth.device_id());
+        let _: serde_json::Value = self.http_client.post(&path, rotation.request()).await?;
+
+        let key_id = rotation.key_id().to_string();
+        auth.complete_key_rotation(rotation)?;
+
+        Ok(key_id)
+    }
+
     /// List devices
     pub async fn list_devices(&self, limit: Option<u32>, offset: Option<u32>) -> Result<Vec<DeviceInfo>> {
         let mut path = "/devices?".to_string();
@@ -0,0 +1,161 @@
+//! Error types for the IoT SDK
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Result type used throughout the SDK
+pub type Result<T> = std::result::Result<T, SdkError>;
+
+/// Error object returned by the dashboard API in non-2xx responses
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ApiError {
+    /// Machine-readable error code
+    #[serde(default)]
+    pub code: Option<String>,
+
+    /// Short error identifier or summary
+    #[serde(default)]
+    pub error: Option<String>,
+
+    /// Human-readable error message
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Errors returned by the SDK
+#[derive(Debug, Error)]
+pub enum SdkError {
+    /// The HTTP request could not be sent or its response could not be read
+    #[error("HTTP transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// The API answered with a non-success status code
+    #[error("API error {status}: {body}")]
+    Http {
+        status: u16,
+        body: String,
+        error: Option<Box<ApiError>>,
+
+        /// Delay requested by the server's `Retry-After` header, when given
+        /// in seconds
+        retry_after: Option<Duration>,
+    },
+
+    /// Authentication failed or a token could not be created
+    #[error("Authentication error: {0}")]
+    Auth(String),
+
+    /// A token failed verification
+    #[error("Invalid token: {0}")]
+    Token(#[from] crate::auth::TokenError),
+
+    /// A key could not be loaded, parsed or generated
+    #[error("Key error: {0}")]
+    Key(String),
+
+    /// JSON serialization or deserialization failed
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    /// Filesystem or other I/O failure
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The configuration is missing a value or could not be loaded
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    /// The configuration failed validation
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(#[from] crate::config::ValidationError),
+
+    /// The WebSocket handshake or stream failed
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] Box<tokio_tungstenite::tungstenite::Error>),
+
+    /// The WebSocket is not connected or was closed
+    #[error("WebSocket is not connected")]
+    Disconnected,
+
+    /// The operation did not complete in time
+    #[error("Operation timed out")]
+    Timeout,
+
+    /// The offline queue reached its size limit
+    #[error("Offline queue is full")]
+    QueueFull,
+
+    /// The API is failing and the circuit breaker is rejecting requests
+    #[error("Circuit breaker is open, retry in {retry_after:?}")]
+    CircuitOpen {
+        /// Time left until the breaker lets a probe request through
+        retry_after: Duration,
+    },
+}
+
+impl SdkError {
+    /// HTTP status code, if this error came from an API response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            SdkError::Http { status, .. } => Some(*status),
+            SdkError::Transport(e) => e.status().map(|s| s.as_u16()),
+            _ => None,
+        }
+    }
+
+    /// Parsed server error object, if the API returned one
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            SdkError::Http { error, .. } => error.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the API rejected the request's credentials (401)
+    pub fn is_unauthorized(&self) -> bool {
+        self.status() == Some(401)
+    }
+
+    /// Whether the failure is likely transient, so the request may succeed
+    /// if retried
+    ///
+    /// Connection errors, timeouts, `429 Too Many Requests` and `5xx`
+    /// responses are retryable; other `4xx` responses are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SdkError::Transport(e) => match e.status() {
+                Some(status) => is_retryable_status(status.as_u16()),
+                None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+            },
+            SdkError::Http { status, .. } => is_retryable_status(*status),
+            SdkError::Timeout => true,
+            _ => false,
+        }
+    }
+
+    /// Delay the server asked for before retrying, from `Retry-After`, or
+    /// the time until an open circuit breaker lets requests through again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SdkError::Http { retry_after, .. } => *retry_after,
+            SdkError::CircuitOpen { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// Whether the requested resource does not exist (404)
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(404)
+    }
+}
+
+fn is_retryable_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for SdkError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        SdkError::WebSocket(Box::new(e))
+    }
+}
@@ -8,4 +8,9 @@ pub mod models;
 pub mod communication;
 pub mod webhooks;
 pub mod config;
-pub mod device;
\ No newline at end of file
+pub mod device;
+pub mod client;
+pub mod error;
+
+pub use client::IotClient;
+pub use error::{Result, SdkError};
\ No newline at end of file
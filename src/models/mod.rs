@@ -84,7 +84,7 @@ impl DeviceData {
     }
 
     /// Add a sensor reading
-    pub fn add_reading<T: Serialize>(mut self, name: &str, value: T) -> anyhow::Result<Self> {
+    pub fn add_reading<T: Serialize>(mut self, name: &str, value: T) -> crate::error::Result<Self> {
         let value = serde_json::to_value(value)?;
         self.readings.insert(name.to_string(), value);
         Ok(self)
@@ -1,6 +1,6 @@
 //! Webhook management for real-time data updates
 
-use anyhow::Result;
+use crate::error::Result;
 use serde::{Serialize, Deserialize};
 use crate::communication::http::HttpClient;
 use tracing::info;
@@ -55,7 +55,34 @@ impl WebhookManager {
     }
 
     /// Register a new webhook
+    ///
+    /// Retries carry the same idempotency key, so the server can drop
+    /// duplicate webhooks.
     pub async fn register_webhook(&self, url: &str,  device_id: &str, events: Vec<WebhookEventType>) -> Result<Webhook> {
+        self.register(url, device_id, events, None).await
+    }
+
+    /// Register a new webhook with a caller-chosen idempotency key
+    ///
+    /// Reusing the key across application retries or restarts lets the
+    /// server recognize a webhook it has already created.
+    pub async fn register_webhook_with_idempotency_key(
+        &self,
+        url: &str,
+        device_id: &str,
+        events: Vec<WebhookEventType>,
+        idempotency_key: &str,
+    ) -> Result<Webhook> {
+        self.register(url, device_id, events, Some(idempotency_key)).await
+    }
+
+    async fn register(
+        &self,
+        url: &str,
+        device_id: &str,
+        events: Vec<WebhookEventType>,
+        idempotency_key: Option<&str>,
+    ) -> Result<Webhook> {
         let payload = serde_json::json!({
             "url": url,
             "device_id": device_id,
@@ -64,7 +91,10 @@ impl WebhookManager {
         
         info!("Registering webhook for device {} at URL {}", device_id, url);
         
-        let webhook: Webhook = self.http_client.post("/webhooks", &payload).await?;
+        let webhook: Webhook = match idempotency_key {
+            Some(key) => self.http_client.post_with_idempotency_key("/webhooks", &payload, key).await?,
+            None => self.http_client.post("/webhooks", &payload).await?,
+        };
         
         info!("Successfully registered webhook with ID {}", webhook.id);
         